@@ -1,10 +1,62 @@
+use std::marker::PhantomData;
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
 use bevy_kira_audio::AudioSource;
 use enum_map::{enum_map, EnumMap};
+use serde::de::DeserializeOwned;
 
-use crate::{construct::Construct, item::Item, prelude::*};
+use crate::{
+    construct::Construct, item::Item, loot::LootTables, map::StructureTemplates, prelude::*,
+};
 
 pub fn asset_plugin(app: &mut App) {
-    app.add_startup_system(load.in_base_set(StartupSet::PreStartup));
+    app.fn_plugin(ron_asset_plugin::<StructureTemplates>)
+        .fn_plugin(ron_asset_plugin::<LootTables>)
+        .add_startup_system(load.in_base_set(StartupSet::PreStartup));
+}
+
+/// An asset that designers edit as RON, checked when it's loaded
+pub trait RonAsset: TypeUuid + Send + Sync + Sized + 'static {
+    type Raw: DeserializeOwned;
+
+    /// Full extension, without the leading dot, like `structures.ron`
+    const EXTENSION: &'static str;
+
+    fn from_raw(raw: Self::Raw) -> Result<Self, bevy::asset::Error>;
+
+    /// For loading outside of the asset server
+    fn from_ron(ron: &str) -> Result<Self, bevy::asset::Error> {
+        Self::from_raw(ron::from_str(ron)?)
+    }
+}
+
+struct RonLoader<T>(PhantomData<fn() -> T>);
+
+impl<T: RonAsset> AssetLoader for RonLoader<T> {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let asset = T::from_raw(ron::de::from_bytes(bytes)?)?;
+            load_context.set_default_asset(LoadedAsset::new(asset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        std::slice::from_ref(&T::EXTENSION)
+    }
+}
+
+pub fn ron_asset_plugin<T: RonAsset>(app: &mut App) {
+    app.add_asset::<T>()
+        .add_asset_loader(RonLoader::<T>(PhantomData));
 }
 
 #[derive(Resource)]
@@ -19,6 +71,8 @@ pub struct GameAssets {
     pub nuclear_bullet: Handle<Image>,
     pub turret_bullet: Handle<Image>,
     pub player: [Handle<Image>; 4],
+    pub structures: Handle<StructureTemplates>,
+    pub loot: Handle<LootTables>,
 }
 
 fn load(mut commands: Commands, asset_server: Res<AssetServer>) {
@@ -39,6 +93,8 @@ fn load(mut commands: Commands, asset_server: Res<AssetServer>) {
             Item::Assembler => assembler_on.clone(),
             Item::Generator => generator_item.clone(),
             Item::Turret => turret_left_on.clone(),
+            Item::Wall => asset_server.load("art/wall_item.png"),
+            Item::Door => asset_server.load("art/door_item.png"),
         },
         empty_item: asset_server.load("art/empty_item.png"),
         constructs: enum_map! {
@@ -69,5 +125,7 @@ fn load(mut commands: Commands, asset_server: Res<AssetServer>) {
             asset_server.load("art/player/south_east.png"),
             asset_server.load("art/player/south_west.png"),
         ],
+        structures: asset_server.load("gen/base.structures.ron"),
+        loot: asset_server.load("gen/base.loot.ron"),
     })
 }
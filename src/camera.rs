@@ -1,10 +1,17 @@
-use crate::prelude::*;
+use bevy::transform::TransformSystem;
+
+use crate::{player::Player, prelude::*, timestep::TimestepSet};
 
 #[derive(Component)]
 pub struct PlayerCamera;
 
 pub fn camera_plugin(app: &mut App) {
-    app.add_startup_system(init);
+    app.add_startup_system(init).add_system(
+        follow_player
+            .in_base_set(CoreSet::PostUpdate)
+            .after(TimestepSet::Interpolate)
+            .before(TransformSystem::TransformPropagate),
+    );
 }
 
 fn init(mut commands: Commands) {
@@ -19,3 +26,26 @@ fn init(mut commands: Commands) {
         PlayerCamera,
     ));
 }
+
+/// Follows where the player is drawn, so they don't jitter on screen
+fn follow_player(
+    players: Query<&Transform, With<Player>>,
+    mut cameras: Query<&mut Transform, (With<PlayerCamera>, Without<Player>)>,
+    time: Res<Time>,
+) {
+    let Ok(transform) = players.get_single() else { return };
+
+    let camera_translation = &mut cameras.single_mut().translation;
+    let target = transform
+        .translation
+        .truncate()
+        .extend(camera_translation.z);
+    let dir = (target - *camera_translation).truncate();
+    let l = dir.length();
+    const CAM_SPEED: f32 = 8.0;
+    let m = if l == 0.0 { Vec2::ZERO } else { dir / l }
+        * (l * time.delta_seconds() * CAM_SPEED)
+            .max(time.delta_seconds() * CAM_SPEED)
+            .min(l);
+    *camera_translation += m.extend(0.0);
+}
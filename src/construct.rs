@@ -11,21 +11,23 @@ use crate::{
     ecs::DynBundle,
     entities::{EnemyMarker, Facing, Lifetime},
     item::{remove_item_at, Inventory, InventorySlot, Item, INTERACT_RADIUS},
-    map::as_object_vec3,
-    physics::{DespawnOnCollide, Vel},
+    map::{as_object_vec3, ChunkDeltas, ChunkManager, ChunkQuery, Object, WallTile},
+    physics::{Collider, CollisionLayers, Vel},
     player::Player,
     prelude::*,
-    stats::{Health, RadiationSource},
+    projectile::{Faction, HitEffect, Projectile},
+    spatial::{spatial_plugin, SpatialIndex},
+    stats::{DamageKind, Health, RadiationSource},
 };
 
 pub fn construct_plugin(app: &mut App) {
-    app.add_system(update_generators)
+    app.fn_plugin(spatial_plugin::<PowerSource>)
         .add_system(update_generator_sprites)
-        .add_system(set_power)
         .add_system(update_assemblers)
         .add_system(update_turret_sprites)
-        .add_system(turret_shoot)
-        .add_system(enemies_hit_bullets);
+        .add_systems(
+            (update_generators, set_power, turret_shoot).in_schedule(CoreSchedule::FixedUpdate),
+        );
 }
 
 #[derive(Clone, Component, Copy, Enum)]
@@ -63,6 +65,13 @@ impl Construct {
             },
             self,
             AudioEmitter { instances: vec![] },
+            Collider::Aabb(Vec2::splat(12.)),
+            CollisionLayers::new(
+                CollisionLayers::CONSTRUCT,
+                CollisionLayers::PLAYER | CollisionLayers::ENEMY | CollisionLayers::ENEMY_BULLET,
+            ),
+            Faction::Player,
+            Health::default(),
         );
 
         match self {
@@ -133,11 +142,15 @@ pub fn spawn_construct(slot: usize, construct: Construct) -> impl Fn(&mut World)
             }
         }
 
-        let construct = construct.bundle(transform.translation.truncate(), &assets);
+        let pos = transform.translation.truncate();
+        let bundle = construct.bundle(pos, &assets);
 
         remove_item_at(slot, &mut slots, inventory.single());
 
-        construct.world_spawn(world);
+        let object = world
+            .resource_mut::<ChunkDeltas>()
+            .place(pos, Object::Construct(construct));
+        bundle.world_spawn(world).insert(object.bundle());
     }
 }
 
@@ -176,11 +189,11 @@ pub fn fuel_generator(slot: usize) -> impl Fn(&mut World) {
 
 fn update_generators(
     mut generators: Query<(&mut Generator, &mut PowerSource, &mut RadiationSource)>,
-    time: Res<Time>,
+    time: Res<FixedTime>,
 ) {
     for (mut generator, mut source, mut radiation) in &mut generators {
         if generator.fuel > 0. {
-            generator.fuel -= time.delta_seconds();
+            generator.fuel -= time.period.as_secs_f32();
 
             if generator.fuel <= 0. {
                 **source = false;
@@ -222,90 +235,83 @@ fn update_turret_sprites(
     }
 }
 
-#[derive(Component)]
-struct FriendlyBullet;
+const TURRET_RANGE: f32 = 192.;
 
 fn turret_shoot(
     mut commands: Commands,
-    mut turrets: Query<(&Transform, &PowerConsumer, &mut Facing, &mut Turret)>,
-    enemies: Query<&Transform, With<EnemyMarker>>,
+    mut turrets: Query<(Entity, &Transform, &PowerConsumer, &mut Facing, &mut Turret)>,
+    enemy_index: Res<SpatialIndex<EnemyMarker>>,
     assets: Res<GameAssets>,
-    time: Res<Time>,
+    time: Res<FixedTime>,
+    chunks: Res<ChunkManager>,
+    chunk_query: ChunkQuery,
+    walls: Query<&WallTile>,
 ) {
-    for (&transform, consumer, mut facing, mut turret) in &mut turrets {
+    for (entity, &transform, consumer, mut facing, mut turret) in &mut turrets {
         if consumer.source.is_none() {
             turret.timer = 0.;
             continue;
         }
 
-        turret.timer += time.delta_seconds();
+        turret.timer += time.period.as_secs_f32();
 
         if turret.timer < 1. {
             continue;
         }
         turret.timer = 0.;
 
-        let mut closest = None;
-        let mut closest_distance = f32::INFINITY;
-
         let pos = transform.translation.xy();
 
-        for enemy_transform in &enemies {
-            let enemy_pos = enemy_transform.translation.xy();
-            let distance = pos.distance_squared(enemy_pos);
-
-            if distance < closest_distance {
-                closest = Some(enemy_pos);
-                closest_distance = distance;
-                *facing = match enemy_pos.x < pos.x {
-                    true => Facing::Left,
-                    false => Facing::Right,
-                };
-            }
-        }
-
-        if closest_distance > 192. * 192. {
-            continue;
-        }
+        // Don't shoot through walls
+        let visible =
+            |_, enemy_pos| chunks.line_of_sight(pos, enemy_pos, &chunk_query, &walls, true);
+        let closest = enemy_index.nearest(pos, TURRET_RANGE, visible);
+        let Some((_, closest)) = closest else { continue };
 
-        let Some(closest) = closest else { continue };
+        *facing = match closest.x < pos.x {
+            true => Facing::Left,
+            false => Facing::Right,
+        };
 
-        commands.spawn((
-            SpriteBundle {
-                texture: assets.turret_bullet.clone(),
-                transform,
-                ..default()
-            },
-            Vel((closest - pos).normalize_or_zero() * 500.),
-            Lifetime(5.),
-            DespawnOnCollide,
-            FriendlyBullet,
-        ));
+        Projectile::new(
+            Faction::Player,
+            6.,
+            0,
+            vec![
+                HitEffect::Damage(0.4, DamageKind::Kinetic),
+                HitEffect::Knockback(200.),
+            ],
+        )
+        .with_source(entity)
+        .spawn(
+            (
+                SpriteBundle {
+                    texture: assets.turret_bullet.clone(),
+                    transform,
+                    ..default()
+                },
+                Vel((closest - pos).normalize_or_zero() * 500.),
+                Lifetime(5.),
+            ),
+            &mut commands,
+        );
     }
 }
 
 const POWER_RANGE: f32 = 128.;
 
-// Optimize this if it gets laggy
 fn set_power(
-    sources: Query<(Entity, &Transform, &PowerSource)>,
+    sources: Query<&PowerSource>,
+    source_index: Res<SpatialIndex<PowerSource>>,
     mut consumers: Query<(&Transform, &mut PowerConsumer)>,
 ) {
-    'outer: for (consumer_transform, mut consumer) in &mut consumers {
-        for (source, source_transform, source_power) in &sources {
-            if source_transform
-                .translation
-                .truncate()
-                .distance_squared(consumer_transform.translation.truncate())
-                < POWER_RANGE * POWER_RANGE
-                && **source_power
-            {
-                consumer.source = Some(source);
-                continue 'outer;
-            }
-        }
+    let powered = |source, _| matches!(sources.get(source), Ok(&PowerSource(true)));
 
-        consumer.source = None;
+    for (consumer_transform, mut consumer) in &mut consumers {
+        let pos = consumer_transform.translation.truncate();
+        consumer.source = source_index
+            .nearest(pos, POWER_RANGE, powered)
+            .map(|(source, _)| source);
     }
 }
 
@@ -341,21 +347,3 @@ fn update_assemblers(
         };
     }
 }
-
-fn enemies_hit_bullets(
-    mut commands: Commands,
-    mut enemies: Query<(&mut Health, &Transform), With<EnemyMarker>>,
-    bullets: Query<(Entity, &Transform), With<FriendlyBullet>>,
-) {
-    for (mut health, transform) in &mut enemies {
-        for (bullet, bullet_transform) in &bullets {
-            if (transform.translation.truncate() - bullet_transform.translation.truncate())
-                .length_squared()
-                < 30. * 30.
-            {
-                **health -= 0.4;
-                commands.entity(bullet).despawn();
-            }
-        }
-    }
-}
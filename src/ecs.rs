@@ -8,7 +8,7 @@ pub trait DynBundle {
         self: Box<Self>,
         commands: &'a mut Commands<'w, 's>,
     ) -> EntityCommands<'w, 's, 'a>;
-    fn world_spawn(self: Box<Self>, world: &mut World) -> EntityMut;
+    fn world_spawn(self: Box<Self>, world: &mut World) -> EntityMut<'_>;
 }
 
 impl<T: Bundle> DynBundle for T {
@@ -19,7 +19,7 @@ impl<T: Bundle> DynBundle for T {
         commands.spawn(*self)
     }
 
-    fn world_spawn(self: Box<Self>, world: &mut World) -> EntityMut {
+    fn world_spawn(self: Box<Self>, world: &mut World) -> EntityMut<'_> {
         world.spawn(*self)
     }
 }
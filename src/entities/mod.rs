@@ -5,32 +5,44 @@ use rand::{thread_rng, Rng};
 
 use crate::{
     asset::GameAssets,
-    map::as_object_vec3,
-    physics::{DespawnOnCollide, Vel},
+    game_over::RunStats,
+    loot::DropsLoot,
+    map::{
+        as_object_vec3, wpos_to_cpos, ChunkManager, ChunkQuery, InChunk, Path, Pathfinder, WallTile,
+    },
+    physics::{Collider, CollisionLayers, Impulses, Vel, Walker},
     player::Player,
     prelude::*,
-    stats::{stat_propegation, RadiationSource, Stat, StatBundle, Stats},
+    projectile::{Faction, HitEffect, Projectile},
+    spatial::spatial_plugin,
+    stats::{
+        Buff, BuffKind, Effect, EffectKind, RadiationSource, Stacking, Stat, StatBundle, Stats,
+    },
 };
 use enum_map::enum_map;
 
 pub fn animation_plugin(app: &mut App) {
     app.fn_plugin(state_machine_plugin)
+        .fn_plugin(spatial_plugin::<EnemyMarker>)
         .fn_plugin(trigger_plugin::<RandomTrigger>)
         .fn_plugin(trigger_plugin::<NearPlayer>)
+        .fn_plugin(trigger_plugin::<SeesPlayer>)
         .add_startup_system(init)
         .add_system(animation)
-        .add_system(follow_player_test)
-        .add_system(spawn_rustaches)
-        .add_systems(
-            (update_facing, apply_system_buffers)
-                .chain()
-                .before(stat_propegation),
-        )
+        .add_systems((update_facing, apply_system_buffers).chain())
         .add_system(play_animation)
-        .add_system(wander)
-        .add_system(follow)
-        .add_system(fire)
-        .add_system(lifetime);
+        .add_systems(
+            (
+                follow_player_test,
+                spawn_rustaches,
+                wander,
+                follow,
+                stand_still,
+                fire,
+                lifetime,
+            )
+                .in_schedule(CoreSchedule::FixedUpdate),
+        );
 
     app.register_type::<Animation>();
 }
@@ -38,26 +50,45 @@ pub fn animation_plugin(app: &mut App) {
 #[derive(Component, Default, Deref, DerefMut)]
 struct WanderDirection(Option<Vec2>);
 
+/// Per second, on average
+const WANDER_TOGGLE_RATE: f32 = 0.6;
+
+fn chance(rate: f32, dt: f32) -> f64 {
+    1. - (-rate as f64 * dt as f64).exp()
+}
+
 fn follow_player_test(
     player: Query<&Transform, With<Player>>,
-    mut enemies: Query<(&Transform, &Stats, &mut Vel, &mut WanderDirection), Without<Player>>,
+    mut enemies: Query<
+        (
+            &Transform,
+            &Stats,
+            &mut Vel,
+            &mut WanderDirection,
+            &mut Path,
+        ),
+        Without<Player>,
+    >,
+    pathfinder: Pathfinder,
+    time: Res<FixedTime>,
 ) {
     let Ok(player_transform) = player.get_single() else { return };
     let player_pos = player_transform.translation.xy();
+    let dt = time.period.as_secs_f32();
+    let toggle_chance = chance(WANDER_TOGGLE_RATE, dt);
     let mut rng = rand::thread_rng();
-    for (transform, stats, mut vel, mut direction) in enemies.iter_mut() {
+    for (transform, stats, mut vel, mut direction, mut path) in enemies.iter_mut() {
         let pos = transform.translation.xy();
         if pos.distance_squared(player_pos) < 256.0 * 256.0 {
             **direction = None;
-            vel.0 = (player_pos - pos).normalize_or_zero() * stats.get(Stat::Speed);
+            vel.0 = path.direction(pos, player_pos, &pathfinder, dt) * stats.get(Stat::Speed);
         } else if let Some(dir) = **direction {
-            // TODO: Make this framerate independent
-            if rng.gen_bool(0.01) {
+            if rng.gen_bool(toggle_chance) {
                 **direction = None;
             } else {
                 vel.0 = dir.normalize_or_zero() * stats.get(Stat::Speed);
             }
-        } else if rng.gen_bool(0.01) {
+        } else if rng.gen_bool(toggle_chance) {
             **direction = Some(
                 Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize_or_zero(),
             );
@@ -271,28 +302,40 @@ pub fn spawn_slime<'w, 's, 'a>(
             playing: Playing::default(),
         },
         Vel::default(),
+        Walker::default(),
+        Impulses::default(),
+        Collider::Circle(10.),
+        CollisionLayers::new(
+            CollisionLayers::ENEMY,
+            CollisionLayers::PLAYER
+                | CollisionLayers::ENEMY
+                | CollisionLayers::CONSTRUCT
+                | CollisionLayers::FRIENDLY_BULLET,
+        ),
+        Faction::Enemy,
         WanderDirection::default(),
+        Path::default(),
         RadiationSource {
             strength: 0.04,
             radius: 128.,
             active: true,
         },
         EnemyMarker,
+        DropsLoot("slime".to_owned()),
     ))
 }
 
 fn spawn_rustache<'w, 's, 'a>(
     commands: &'a mut Commands<'w, 's>,
     atlases: &TextureAtlases,
-    player_pos: Vec2,
+    position: Vec2,
+    direction: Vec2,
 ) -> EntityCommands<'w, 's, 'a> {
-    let disp = Vec2::from_angle(thread_rng().gen_range(0.0..TAU));
-
     commands.spawn((
         SpriteSheetBundle {
             sprite: TextureAtlasSprite::new(0),
             texture_atlas: atlases.rustache.clone(),
-            transform: Transform::from_translation(as_object_vec3(player_pos + disp * 384.))
+            transform: Transform::from_translation(as_object_vec3(position))
                 .with_scale(Vec2::splat(2.).extend(1.)),
             ..default()
         },
@@ -315,18 +358,30 @@ fn spawn_rustache<'w, 's, 'a>(
             playing: Playing::default(),
         },
         Vel::default(),
-        StateMachine::new(Wander(-disp))
+        Walker::default(),
+        Impulses::default(),
+        Collider::Aabb(Vec2::new(12., 14.)),
+        CollisionLayers::new(
+            CollisionLayers::ENEMY,
+            CollisionLayers::PLAYER
+                | CollisionLayers::ENEMY
+                | CollisionLayers::CONSTRUCT
+                | CollisionLayers::FRIENDLY_BULLET,
+        ),
+        Faction::Enemy,
+        Path::default(),
+        StateMachine::new(Wander(direction))
             .insert_on_enter::<Wander>(PlayAnimation(2, 3))
-            .trans::<Wander>(RandomTrigger(0.0003), Idle)
+            .trans::<Wander>(RandomTrigger(0.018), Idle)
             .insert_on_enter::<Idle>(PlayAnimation(0, 1))
-            .trans_builder::<Idle, _, _>(RandomTrigger(0.0003), |_| {
+            .trans_builder::<Idle, _, _>(RandomTrigger(0.018), |_| {
                 Some(Wander(Vec2::from_angle(thread_rng().gen_range(0.0..TAU))))
             })
             .trans_builder::<Wander, _, _>(NearPlayer(256.), |&player| Some(Follow(player)))
             .trans_builder::<Idle, _, _>(NearPlayer(256.), |&player| Some(Follow(player)))
             .insert_on_enter::<Follow>(PlayAnimation(2, 3))
             .trans::<Follow>(NotTrigger(NearPlayer(384.)), Idle)
-            .trans_builder::<Follow, _, _>(NearPlayer(192.), |&player| {
+            .trans_builder::<Follow, _, _>(SeesPlayer(192.), |&player| {
                 Some(Fire {
                     target: player,
                     cooldown: 1.5,
@@ -334,15 +389,16 @@ fn spawn_rustache<'w, 's, 'a>(
                 })
             })
             .insert_on_enter::<Fire>(PlayAnimation(0, 1))
-            .trans_builder::<Fire, _, _>(NotTrigger(NearPlayer(256.)), |&player| {
+            .trans_builder::<Fire, _, _>(NotTrigger(SeesPlayer(256.)), |&player| {
                 Some(Follow(player?))
             })
             .trans::<AnyState>(DoneTrigger::Failure, Idle),
-        match disp.x >= 0. {
-            true => Facing::Left,
-            false => Facing::Right,
+        match direction.x >= 0. {
+            true => Facing::Right,
+            false => Facing::Left,
         },
         EnemyMarker,
+        DropsLoot("rustache".to_owned()),
     ))
 }
 
@@ -353,7 +409,6 @@ pub enum Enemy {
 }
 
 impl Enemy {
-    /// If the enemy is Rustache, the position is interpreted as the player position
     pub fn spawn<'w, 's, 'a>(
         &self,
         position: Vec2,
@@ -362,7 +417,12 @@ impl Enemy {
     ) -> EntityCommands<'w, 's, 'a> {
         match self {
             Enemy::Slime => spawn_slime(position, commands, atlases),
-            Enemy::Rustache => spawn_rustache(commands, atlases, position),
+            Enemy::Rustache => spawn_rustache(
+                commands,
+                atlases,
+                position,
+                Vec2::from_angle(thread_rng().gen_range(0.0..TAU)),
+            ),
         }
     }
 }
@@ -370,10 +430,9 @@ impl Enemy {
 #[derive(Clone, Component, Reflect)]
 struct Wander(Vec2);
 
-fn wander(mut wanderers: Query<(&mut Transform, &Wander)>, time: Res<Time>) {
-    for (mut transform, &Wander(direction)) in &mut wanderers {
-        let translation = &mut transform.translation;
-        *translation = as_object_vec3(translation.xy() + direction * 80. * time.delta_seconds());
+fn wander(mut wanderers: Query<(&mut Vel, &Wander)>) {
+    for (mut vel, &Wander(direction)) in &mut wanderers {
+        vel.0 = direction * 80.;
     }
 }
 
@@ -383,26 +442,30 @@ struct Idle;
 #[derive(Clone, Component, Deref, DerefMut, Reflect)]
 struct Follow(Entity);
 
-// TODO Refactor rustaches to use `Vel`
 fn follow(
     mut commands: Commands,
-    followers: Query<(Entity, &Follow)>,
-    mut transforms: Query<&mut Transform>,
-    time: Res<Time>,
+    mut followers: Query<(Entity, &Follow, &Transform, &mut Vel, &mut Path)>,
+    transforms: Query<&Transform>,
+    pathfinder: Pathfinder,
+    time: Res<FixedTime>,
 ) {
-    for (entity, &Follow(target)) in &followers {
-        let Ok([mut transform, target_transform]) = transforms.get_many_mut([entity, target]) else {
+    let dt = time.period.as_secs_f32();
+
+    for (entity, &Follow(target), transform, mut vel, mut path) in &mut followers {
+        let Ok(target_transform) = transforms.get(target) else {
             commands.entity(entity).insert(Done::Failure);
             continue;
         };
 
-        let translation = &mut transform.translation;
-        *translation = as_object_vec3(
-            translation.xy()
-                + (target_transform.translation.xy() - translation.xy()).normalize_or_zero()
-                    * 80.
-                    * time.delta_seconds(),
-        );
+        let pos = transform.translation.xy();
+        let goal = target_transform.translation.xy();
+        vel.0 = path.direction(pos, goal, &pathfinder, dt) * 80.;
+    }
+}
+
+fn stand_still(mut standers: Query<&mut Vel, Or<(With<Idle>, With<Fire>)>>) {
+    for mut vel in &mut standers {
+        vel.0 = Vec2::ZERO;
     }
 }
 
@@ -413,18 +476,15 @@ struct Fire {
     timer: f32,
 }
 
-#[derive(Component)]
-pub struct EnemyBullet;
-
 fn fire(
     mut commands: Commands,
     mut firers: Query<(Entity, &mut Fire)>,
     transforms: Query<&Transform>,
-    time: Res<Time>,
+    time: Res<FixedTime>,
     assets: Res<GameAssets>,
 ) {
     for (entity, mut fire) in &mut firers {
-        fire.timer += time.delta_seconds();
+        fire.timer += time.period.as_secs_f32();
         if fire.timer < fire.cooldown {
             continue;
         }
@@ -435,33 +495,52 @@ fn fire(
             continue;
         };
 
-        commands.spawn((
-            SpriteBundle {
-                texture: assets.nuclear_bullet.clone(),
-                transform,
-                ..default()
+        let effects = vec![
+            HitEffect::Radiation(0.1),
+            HitEffect::Knockback(300.),
+            HitEffect::DamageWall(1.),
+            HitEffect::Buff(Buff::new(
+                BuffKind::Slowed,
+                Effect::new(EffectKind::Mul(Stat::Speed), 0.6),
+                2.,
+                Stacking::Refresh,
+            )),
+            HitEffect::Explode {
+                radius: 40.,
+                damage: 4.,
+                knockback: 250.,
             },
-            Vel(
-                (target_transform.translation.xy() - transform.translation.xy())
-                    .normalize_or_zero()
-                    * 200.,
-            ),
-            Lifetime(5.),
-            DespawnOnCollide,
-            EnemyBullet,
-        ));
+        ];
+        Projectile::new(Faction::Enemy, 6., 0, effects)
+            .with_source(entity)
+            .spawn(
+                (
+                    SpriteBundle {
+                        texture: assets.nuclear_bullet.clone(),
+                        transform,
+                        ..default()
+                    },
+                    Vel(
+                        (target_transform.translation.xy() - transform.translation.xy())
+                            .normalize_or_zero()
+                            * 200.,
+                    ),
+                    Lifetime(5.),
+                ),
+                &mut commands,
+            );
     }
 }
 
-// TODO Make framerate independent
+/// Fires at random, on average this many times per second
 #[derive(Deref, DerefMut, Reflect)]
 struct RandomTrigger(f32);
 
 impl BoolTrigger for RandomTrigger {
-    type Param<'w, 's> = ();
+    type Param<'w, 's> = Res<'w, Time>;
 
-    fn trigger(&self, _: Entity, (): &Self::Param<'_, '_>) -> bool {
-        thread_rng().gen::<f32>() < **self
+    fn trigger(&self, _: Entity, time: &Self::Param<'_, '_>) -> bool {
+        thread_rng().gen_bool(chance(**self, time.delta_seconds()))
     }
 }
 
@@ -494,6 +573,36 @@ impl Trigger for NearPlayer {
     }
 }
 
+/// Near the player, with no walls in between
+#[derive(Deref, DerefMut, Reflect)]
+struct SeesPlayer(f32);
+
+impl Trigger for SeesPlayer {
+    type Param<'w, 's> = (
+        <NearPlayer as Trigger>::Param<'w, 's>,
+        Res<'w, ChunkManager>,
+        ChunkQuery<'w, 's, 'static>,
+        Query<'w, 's, &'static WallTile>,
+    );
+    type Ok = Entity;
+    type Err = Option<Entity>;
+
+    fn trigger(
+        &self,
+        entity: Entity,
+        (near_param, chunks, chunk_query, walls): &Self::Param<'_, '_>,
+    ) -> Result<Entity, Option<Entity>> {
+        let player = NearPlayer(**self).trigger(entity, near_param)?;
+        let (transforms, _) = near_param;
+        let pos = transforms.get(entity).unwrap().translation.xy();
+        let player_pos = transforms.get(player).unwrap().translation.xy();
+        chunks
+            .line_of_sight(pos, player_pos, chunk_query, walls, false)
+            .then_some(player)
+            .ok_or(Some(player))
+    }
+}
+
 // Incredibly jank
 fn update_facing(
     mut commands: Commands,
@@ -561,20 +670,23 @@ fn spawn_rustaches(
     mut timer: Local<Repeating>,
     players: Query<&Transform, With<Player>>,
     atlases: Res<TextureAtlases>,
-    time: Res<Time>,
+    run_stats: Res<RunStats>,
+    time: Res<FixedTime>,
 ) {
-    **timer += time.delta_seconds();
+    **timer += time.period.as_secs_f32();
     if **timer < 1. {
         return;
     }
     **timer -= 1.;
 
-    if thread_rng().gen_bool(1. / (time.elapsed_seconds_f64() / 400. + 1.).sqrt()) {
+    if thread_rng().gen_bool(1. / (run_stats.time as f64 / 400. + 1.).sqrt()) {
         return;
     }
 
     let Ok(player_pos) = players.get_single() else { return };
-    Enemy::Rustache.spawn(player_pos.translation.xy(), &mut commands, &atlases);
+    let disp = Vec2::from_angle(thread_rng().gen_range(0.0..TAU));
+    let pos = player_pos.translation.xy() + disp * 384.;
+    spawn_rustache(&mut commands, &atlases, pos, -disp).insert(InChunk(wpos_to_cpos(pos)));
 }
 
 #[derive(Component, Deref, DerefMut)]
@@ -583,10 +695,10 @@ pub struct Lifetime(pub f32);
 fn lifetime(
     mut commands: Commands,
     mut lifetimes: Query<(Entity, &mut Lifetime)>,
-    time: Res<Time>,
+    time: Res<FixedTime>,
 ) {
     for (entity, mut lifetime) in &mut lifetimes {
-        **lifetime -= time.delta_seconds();
+        **lifetime -= time.period.as_secs_f32();
         if **lifetime <= 0. {
             commands.entity(entity).despawn();
         }
@@ -0,0 +1,243 @@
+use bevy::{ecs::system::SystemState, utils::HashSet};
+
+use crate::{
+    asset::GameAssets,
+    camera::PlayerCamera,
+    entities::EnemyMarker,
+    item::InventorySlot,
+    map::unload_world,
+    physics::Vel,
+    player::{spawn_player, Action, Player},
+    prelude::*,
+    projectile::{Faction, Projectile},
+    seed::WorldSeed,
+    stats::{take_damage, Damage, DamageKind, Health, Stats},
+};
+
+pub fn game_over_plugin(app: &mut App) {
+    app.init_resource::<RunStats>()
+        .add_systems(
+            (
+                track_run.after(take_damage),
+                detect_death.after(take_damage),
+            )
+                .in_schedule(CoreSchedule::FixedUpdate),
+        )
+        .add_system(show_game_over)
+        .add_system(restart_buttons);
+}
+
+#[derive(Clone, Copy, Default, Resource)]
+pub struct RunStats {
+    /// Seconds survived
+    pub time: f32,
+    pub kills: u32,
+}
+
+/// Present until the game restarts
+#[derive(Resource)]
+pub struct GameOver {
+    stats: RunStats,
+    cause: Option<DamageKind>,
+}
+
+#[derive(Component)]
+struct GameOverScreen;
+
+/// Restarts the game when clicked. Rolls a new seed if it's `true`.
+#[derive(Component)]
+struct RestartButton(bool);
+
+fn track_run(
+    mut run_stats: ResMut<RunStats>,
+    players: Query<(), With<Player>>,
+    enemies: Query<&Health, With<EnemyMarker>>,
+    factions: Query<&Faction>,
+    mut damages: EventReader<Damage>,
+    game_over: Option<Res<GameOver>>,
+    time: Res<FixedTime>,
+) {
+    if players.is_empty() || game_over.is_some() {
+        damages.clear();
+        return;
+    }
+
+    run_stats.time += time.period.as_secs_f32();
+
+    let mut killed = HashSet::new();
+    for damage in damages.iter() {
+        let Ok(health) = enemies.get(damage.target) else { continue };
+        let Some(source) = damage.source else { continue };
+        if **health <= 0. && matches!(factions.get(source), Ok(Faction::Player)) {
+            killed.insert(damage.target);
+        }
+    }
+    run_stats.kills += killed.len() as u32;
+}
+
+fn detect_death(
+    mut commands: Commands,
+    mut players: Query<(Entity, &Health, &Stats, &mut Vel), With<Player>>,
+    mut damages: EventReader<Damage>,
+    run_stats: Res<RunStats>,
+    game_over: Option<Res<GameOver>>,
+    mut last_health: Local<Option<(Entity, f32)>>,
+) {
+    let Ok((player, health, stats, mut vel)) = players.get_single_mut() else {
+        damages.clear();
+        return;
+    };
+
+    // `take_damage` already applied this tick's damage, so replay it from last tick's health to
+    // find the blow that took the player's health to zero
+    let mut running = match *last_health {
+        Some((entity, health)) if entity == player => health,
+        _ => *Health::default(),
+    };
+    *last_health = Some((player, **health));
+
+    let mut cause = None;
+    for damage in damages.iter().filter(|damage| damage.target == player) {
+        let alive = running > 0.;
+        running -= stats.calc_resisted(damage.amount, damage.kind);
+        if alive && running <= 0. {
+            cause = Some(damage.kind);
+        }
+    }
+
+    if **health > 0. || game_over.is_some() {
+        return;
+    }
+
+    commands.insert_resource(GameOver {
+        stats: *run_stats,
+        cause,
+    });
+    // The body stays until `restart` despawns it, but can't be controlled
+    commands.entity(player).remove::<ActionState<Action>>();
+    vel.0 = Vec2::ZERO;
+}
+
+fn show_game_over(
+    mut commands: Commands,
+    game_over: Option<Res<GameOver>>,
+    seed: Res<WorldSeed>,
+    asset_server: Res<AssetServer>,
+) {
+    let Some(game_over) = game_over else { return };
+    if !game_over.is_added() {
+        return;
+    }
+
+    let font = asset_server.load("font/FiraSans-Bold.ttf");
+    let text = |text: String, font_size| {
+        TextBundle::from_section(
+            text,
+            TextStyle {
+                font: font.clone(),
+                font_size,
+                color: Color::WHITE,
+            },
+        )
+    };
+
+    let time = game_over.stats.time as u32;
+    let cause = match game_over.cause {
+        Some(cause) => cause.name(),
+        None => "unknown",
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    size: Size::all(Val::Percent(100.)),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.8).into(),
+                ..default()
+            },
+            GameOverScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn(text("You died".to_owned(), 64.));
+            parent.spawn(text(
+                format!("Survived {}:{:02}", time / 60, time % 60),
+                32.,
+            ));
+            parent.spawn(text(format!("Cause of death: {cause}"), 32.));
+            parent.spawn(text(
+                format!("Enemies killed: {}", game_over.stats.kills),
+                32.,
+            ));
+            parent.spawn(text(format!("Seed: {}", **seed), 24.));
+
+            for (label, new_seed) in [("Restart", false), ("New seed", true)] {
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                margin: UiRect::all(Val::Px(8.)),
+                                padding: UiRect::all(Val::Px(8.)),
+                                ..default()
+                            },
+                            background_color: Color::DARK_GRAY.into(),
+                            ..default()
+                        },
+                        RestartButton(new_seed),
+                    ))
+                    .with_children(|button| {
+                        button.spawn(text(label.to_owned(), 32.));
+                    });
+            }
+        });
+}
+
+fn restart_buttons(
+    mut commands: Commands,
+    buttons: Query<(&RestartButton, &Interaction), Changed<Interaction>>,
+) {
+    for (&RestartButton(new_seed), &interaction) in &buttons {
+        if interaction == Interaction::Clicked {
+            commands.add(restart(new_seed));
+        }
+    }
+}
+
+fn restart(new_seed: bool) -> impl Fn(&mut World) {
+    move |world| {
+        if new_seed {
+            **world.resource_mut::<WorldSeed>() = thread_rng().gen();
+        }
+        unload_world(world);
+
+        let mut system_state = SystemState::<(
+            Commands,
+            Query<Entity, Or<(With<GameOverScreen>, With<Player>, With<Projectile>)>>,
+            Query<&mut InventorySlot>,
+            Query<&mut Transform, With<PlayerCamera>>,
+            Res<GameAssets>,
+        )>::new(world);
+        let (mut commands, leftovers, mut slots, mut cameras, assets) = system_state.get_mut(world);
+
+        for entity in &leftovers {
+            commands.entity(entity).despawn_recursive();
+        }
+        for mut slot in &mut slots {
+            **slot = None;
+        }
+        // The world generates around the camera, so it has to be back where the player spawns
+        for mut camera in &mut cameras {
+            camera.translation = Vec3::new(0., 0., camera.translation.z);
+        }
+        spawn_player(&mut commands, &assets);
+
+        system_state.apply(world);
+        world.remove_resource::<GameOver>();
+        world.insert_resource(RunStats::default());
+    }
+}
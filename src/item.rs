@@ -1,17 +1,20 @@
 use bevy::ecs::system::SystemState;
 use enum_map::{enum_map, Enum, EnumMap};
-use rand::distributions::Standard;
+use serde::Deserialize;
 
 use crate::{
     asset::GameAssets,
     construct::{fuel_generator, spawn_construct, Assembler, PowerConsumer},
+    map::{build_wall, ChunkDeltas, ChunkObject, Object},
     player::{Action, Player},
     prelude::*,
+    spatial::{spatial_plugin, SpatialIndex},
     stats::Hunger,
 };
 
 pub fn item_plugin(app: &mut App) {
-    app.init_resource::<Recipes>()
+    app.fn_plugin(spatial_plugin::<Item>)
+        .init_resource::<Recipes>()
         .add_startup_system(init_inventory)
         .add_startup_system(init_recipe_menu)
         .add_system(collect_item)
@@ -22,7 +25,7 @@ pub fn item_plugin(app: &mut App) {
         .add_system(craft_item);
 }
 
-#[derive(Clone, Component, Copy, Enum, Eq, PartialEq)]
+#[derive(Clone, Component, Copy, Deserialize, Enum, Eq, PartialEq)]
 pub enum Item {
     Circuit,
     Metal,
@@ -32,20 +35,8 @@ pub enum Item {
     Generator,
     Assembler,
     Turret,
-}
-
-impl Distribution<Item> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Item {
-        *[
-            Item::Circuit,
-            Item::Metal,
-            Item::CannedFood,
-            Item::Plant,
-            Item::FuelTank,
-        ]
-        .choose(rng)
-        .unwrap()
-    }
+    Wall,
+    Door,
 }
 
 const INVENTORY_SIZE: usize = 10;
@@ -72,6 +63,8 @@ impl Default for Recipes {
             Item::Generator => Some(vec![(Item::Metal, 1)]),
             Item::Assembler => Some(vec![(Item::Circuit, 1)]),
             Item::Turret => Some(vec![(Item::Circuit, 1), (Item::Metal, 1)]),
+            Item::Wall => Some(vec![(Item::Metal, 1)]),
+            Item::Door => Some(vec![(Item::Metal, 1), (Item::Circuit, 1)]),
         })
     }
 }
@@ -150,9 +143,11 @@ pub const INTERACT_RADIUS: f32 = 32.;
 fn collect_item(
     mut commands: Commands,
     players: Query<(&Transform, &ActionState<Action>), With<Player>>,
-    items: Query<(Entity, &Transform, &Item)>,
+    items: Query<(&Item, Option<&ChunkObject>)>,
+    item_index: Res<SpatialIndex<Item>>,
     inventory: Query<&Inventory>,
     mut slots: Query<&mut InventorySlot>,
+    mut deltas: ResMut<ChunkDeltas>,
 ) {
     let Ok((player_transform, action)) = players.get_single() else { return };
     if !action.just_pressed(Action::Collect) {
@@ -160,18 +155,17 @@ fn collect_item(
     }
 
     let player_pos = player_transform.translation.truncate();
-    for (item, item_transform, item_type) in &items {
-        if player_pos.distance_squared(item_transform.translation.truncate())
-            >= INTERACT_RADIUS * INTERACT_RADIUS
-        {
-            continue;
-        }
+    let nearest = item_index.nearest(player_pos, INTERACT_RADIUS, |item, _| items.contains(item));
+    let Some((item, _)) = nearest else { return };
+    let Ok((item_type, object)) = items.get(item) else { return };
 
-        let inventory = inventory.single();
-        if add_item(*item_type, &mut slots, inventory) {
-            commands.entity(item).despawn();
+    let inventory = inventory.single();
+    if add_item(*item_type, &mut slots, inventory) {
+        commands.entity(item).despawn();
+
+        if let Some(&object) = object {
+            deltas.remove(object);
         }
-        break;
     }
 }
 
@@ -213,6 +207,7 @@ fn use_item(
         Generator | Assembler | Turret => {
             commands.add(spawn_construct(slot, item.try_into().unwrap()))
         }
+        Wall | Door => commands.add(build_wall(slot, item.try_into().unwrap())),
         CannedFood => commands.add(eat_food(slot, CANNED_FOOD_VALUE)),
         Circuit | Metal | Plant => (),
     };
@@ -226,6 +221,7 @@ fn drop_item(
     players: Query<&Transform, With<Player>>,
     mouse: Res<Input<MouseButton>>,
     assets: Res<GameAssets>,
+    mut deltas: ResMut<ChunkDeltas>,
 ) {
     if !mouse.just_pressed(MouseButton::Right) {
         return;
@@ -250,6 +246,9 @@ fn drop_item(
                 ..default()
             },
             item,
+            deltas
+                .place(transform.translation.truncate(), Object::Item(item))
+                .bundle(),
         ));
 
         clicked_slot = Some(
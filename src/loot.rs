@@ -0,0 +1,126 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bevy::reflect::TypeUuid;
+use serde::Deserialize;
+
+use crate::{
+    asset::{GameAssets, RonAsset},
+    item::Item,
+    map::{ChunkDeltas, Object},
+    prelude::*,
+    stats::{take_damage, Health},
+};
+
+pub fn loot_plugin(app: &mut App) {
+    app.add_system(
+        drop_loot
+            .after(take_damage)
+            .in_schedule(CoreSchedule::FixedUpdate),
+    );
+}
+
+#[derive(Deserialize)]
+pub struct RawLootTable {
+    /// Weight of picking no item at all
+    #[serde(default)]
+    nothing: u32,
+    items: Vec<(Item, u32)>,
+}
+
+/// Weighted items. `None` means nothing is picked.
+pub struct LootTable(Vec<(Option<Item>, u32)>);
+
+impl LootTable {
+    fn from_raw(name: &str, raw: RawLootTable) -> Result<Self, bevy::asset::Error> {
+        let mut entries = raw
+            .items
+            .into_iter()
+            .map(|(item, weight)| (Some(item), weight))
+            .collect::<Vec<_>>();
+        if raw.nothing > 0 {
+            entries.push((None, raw.nothing));
+        }
+
+        if entries.iter().all(|&(_, weight)| weight == 0) {
+            return Err(bevy::asset::Error::msg(format!(
+                "Loot table `{name}` has no weight"
+            )));
+        }
+
+        Ok(Self(entries))
+    }
+
+    pub fn entries(&self) -> &[(Option<Item>, u32)] {
+        &self.0
+    }
+
+    pub fn roll(&self, rng: &mut impl Rng) -> Option<Item> {
+        self.0
+            .choose_weighted(rng, |&(_, weight)| weight)
+            .expect("Loot tables have weight")
+            .0
+    }
+}
+
+#[derive(Clone, Default, TypeUuid)]
+#[uuid = "6b0f5d1e-3f43-4a8e-9a51-2d7c0b6e88f4"]
+pub struct LootTables(Arc<HashMap<String, LootTable>>);
+
+impl LootTables {
+    pub fn get(&self, name: &str) -> Option<&LootTable> {
+        self.0.get(name)
+    }
+}
+
+impl RonAsset for LootTables {
+    type Raw = HashMap<String, RawLootTable>;
+
+    const EXTENSION: &'static str = "loot.ron";
+
+    fn from_raw(raw: Self::Raw) -> Result<Self, bevy::asset::Error> {
+        Ok(Self(Arc::new(
+            raw.into_iter()
+                .map(|(name, table)| {
+                    let table = LootTable::from_raw(&name, table)?;
+                    Ok((name, table))
+                })
+                .collect::<Result<_, bevy::asset::Error>>()?,
+        )))
+    }
+}
+
+/// Drops an item from the named loot table when its health runs out
+#[derive(Component, Deref, DerefMut)]
+pub struct DropsLoot(pub String);
+
+fn drop_loot(
+    mut commands: Commands,
+    droppers: Query<(Entity, &Transform, &Health, &DropsLoot)>,
+    assets: Res<GameAssets>,
+    loot: Res<Assets<LootTables>>,
+    mut deltas: ResMut<ChunkDeltas>,
+) {
+    let Some(loot) = loot.get(&assets.loot) else { return };
+
+    for (entity, transform, health, drops) in &droppers {
+        if **health > 0. {
+            continue;
+        }
+
+        // Only drop once, even if it takes a while to despawn
+        commands.entity(entity).remove::<DropsLoot>();
+
+        let Some(table) = loot.get(drops) else {
+            warn!("Unknown loot table `{}`", **drops);
+            continue;
+        };
+        let Some(item) = table.roll(&mut thread_rng()) else { continue };
+
+        let pos = transform.translation.truncate();
+        let object = Object::Item(item);
+        let dropped = object.spawn(pos, &mut commands, &assets);
+        commands
+            .entity(dropped)
+            .insert(deltas.place(pos, object).bundle());
+    }
+}
@@ -6,12 +6,18 @@ mod construct;
 mod day_night;
 mod ecs;
 mod entities;
+mod game_over;
 mod item;
+mod loot;
 mod map;
 mod physics;
 mod player;
+mod projectile;
+mod seed;
+mod spatial;
 mod sprite;
 mod stats;
+mod timestep;
 
 use asset::asset_plugin;
 use bevy::{
@@ -23,17 +29,27 @@ use camera::camera_plugin;
 use construct::construct_plugin;
 use day_night::day_night_plugin;
 use entities::animation_plugin;
+use game_over::game_over_plugin;
 use item::item_plugin;
-use map::map_plugin;
+use loot::loot_plugin;
+use map::{dump_world, map_plugin};
 use physics::physics_plugin;
 use player::player_plugin;
+use projectile::projectile_plugin;
+use seed::seed_plugin;
 use stats::stat_plugin;
+use timestep::timestep_plugin;
 
 use prelude::*;
 
 pub const SCREEN_SIZE: (f32, f32) = (768.0, 768.0);
 
 fn main() {
+    if std::env::args().nth(1).as_deref() == Some("dump-world") {
+        dump_world();
+        return;
+    }
+
     let mut app = App::new();
     app.add_plugins(
         DefaultPlugins
@@ -70,15 +86,20 @@ fn main() {
         .insert_resource(SpacialAudio { max_distance: 500. })
         .add_plugin(AudioPlugin)
         .fn_plugin(asset_plugin)
+        .fn_plugin(timestep_plugin)
         .fn_plugin(camera_plugin)
         .fn_plugin(construct_plugin)
         .fn_plugin(item_plugin)
+        .fn_plugin(loot_plugin)
+        .fn_plugin(seed_plugin)
         .fn_plugin(map_plugin)
         .fn_plugin(player_plugin)
         .fn_plugin(day_night_plugin)
         .fn_plugin(stat_plugin)
         .fn_plugin(physics_plugin)
+        .fn_plugin(projectile_plugin)
         .fn_plugin(animation_plugin)
+        .fn_plugin(game_over_plugin)
         .run();
 }
 
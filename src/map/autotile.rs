@@ -0,0 +1,261 @@
+use crate::{prelude::*, seed::WorldSeed};
+
+use super::{
+    gen::{FloorTile, RandomField, WallTile},
+    tile_to_cpos,
+    wall::WallHealth,
+    Chunk, ChunkManager, CHUNK_SIZE,
+};
+
+/// Tiles per row of `art/atlas_floor.png`
+const FLOOR_PER_ROW: u32 = 32;
+/// Tiles per row of `art/atlas_wall.png`
+const WALL_PER_ROW: u32 = 16;
+
+const TRANS_TILE_LUT: [u32; 255] = {
+    let mut table = [0; 255];
+    let mut i = 0;
+
+    loop {
+        if i >= 255 {
+            break;
+        }
+
+        // Don't ask.
+        table[i] = match i as u8 + 1 {
+            bits if bits & 0b01111101 == 0b00000001 => 0,
+            0b00000010 => 1,
+            bits if bits & 0b11110101 == 0b00000100 => 2,
+            bits if bits & 0b01110101 == 0b00000101 => 3,
+            0b00001000 => 4,
+            bits if bits & 0b01111101 == 0b00001001 => 5,
+            0b00001010 => 6,
+            bits if bits & 0b11010111 == 0b00010000 => 7,
+            bits if bits & 0b01010101 == 0b00010001 => 8,
+            bits if bits & 0b11010111 == 0b00010010 => 9,
+            bits if bits & 0b11010101 == 0b00010100 => 10,
+            bits if bits & 0b01010101 == 0b00010101 => 11,
+            0b00100000 => 12,
+            bits if bits & 0b01111101 == 0b00100001 => 13,
+            0b00100010 => 14,
+            bits if bits & 0b11110101 == 0b00100100 => 15,
+            bits if bits & 0b01110101 == 0b00100101 => 16,
+            0b00101000 => 17,
+            bits if bits & 0b01111101 == 0b00101001 => 18,
+            0b00101010 => 19,
+            bits if bits & 0b01011111 == 0b01000000 => 20,
+            bits if bits & 0b01011101 == 0b01000001 => 21,
+            bits if bits & 0b01011111 == 0b01000010 => 22,
+            bits if bits & 0b01010101 == 0b01000100 => 23,
+            bits if bits & 0b01010101 == 0b01000101 => 24,
+            bits if bits & 0b01011111 == 0b01001000 => 25,
+            bits if bits & 0b01011101 == 0b01001001 => 26,
+            bits if bits & 0b01011111 == 0b01001010 => 27,
+            bits if bits & 0b01010111 == 0b01010000 => 28,
+            bits if bits & 0b01010101 == 0b01010001 => 29,
+            bits if bits & 0b01010111 == 0b01010010 => 30,
+            bits if bits & 0b01010101 == 0b01010100 => 31,
+            bits if bits & 0b01010101 == 0b01010101 => 32,
+            0b10000000 => 33,
+            0b10000010 => 34,
+            bits if bits & 0b11110101 == 0b10000100 => 35,
+            0b10001000 => 36,
+            0b10001010 => 37,
+            bits if bits & 0b11010111 == 0b10010000 => 38,
+            bits if bits & 0b11010111 == 0b10010010 => 39,
+            bits if bits & 0b11010101 == 0b10010100 => 40,
+            0b10100000 => 41,
+            0b10100010 => 42,
+            bits if bits & 0b11110101 == 0b10100100 => 43,
+            0b10101000 => 44,
+            0b10101010 => 45,
+            0b00000000 => 0,
+            _ => 0,
+        };
+        i += 1;
+    }
+    table
+};
+
+fn get_trans_tile_idx(sides: u8) -> Option<u32> {
+    sides.checked_sub(1).map(|i| TRANS_TILE_LUT[i as usize])
+}
+
+/// Biome floors reuse the ground and concrete art
+pub fn floor_tint(tile: FloorTile) -> Color {
+    match tile {
+        FloorTile::Ground | FloorTile::Water | FloorTile::Concrete => Color::WHITE,
+        FloorTile::Scorched => Color::rgb(0.55, 0.45, 0.4),
+        FloorTile::Asphalt => Color::rgb(0.45, 0.45, 0.5),
+        FloorTile::Grass => Color::rgb(0.6, 0.85, 0.5),
+        FloorTile::Sludge => Color::rgb(0.6, 0.7, 0.35),
+    }
+}
+
+/// Land next to water gets a shoreline
+pub fn floor_texture_index(
+    tile: FloorTile,
+    pos: IVec2,
+    field: RandomField,
+    neighbour: impl Fn(IVec2) -> FloorTile,
+) -> u32 {
+    let is_water = |x, y| (neighbour(IVec2::new(x, y)) == FloorTile::Water) as u8;
+    let variant = |range| field.gen_range(vek::Vec2::new(pos.x, pos.y), range);
+
+    match tile {
+        FloorTile::Ground | FloorTile::Scorched | FloorTile::Grass | FloorTile::Sludge => {
+            let sides = is_water(1, 0)
+                | is_water(1, 1) << 1
+                | is_water(0, 1) << 2
+                | is_water(-1, 1) << 3
+                | is_water(-1, 0) << 4
+                | is_water(-1, -1) << 5
+                | is_water(0, -1) << 6
+                | is_water(1, -1) << 7;
+            if let Some(idx) = get_trans_tile_idx(sides) {
+                FLOOR_PER_ROW + idx
+            } else {
+                variant(0..=2)
+            }
+        }
+        FloorTile::Water => FLOOR_PER_ROW * 3,
+        FloorTile::Concrete | FloorTile::Asphalt => {
+            FLOOR_PER_ROW * 4
+                + match variant(0..=10) {
+                    1 => 1,
+                    2 => 2,
+                    _ => 0,
+                }
+        }
+    }
+}
+
+/// For an undamaged wall. The column has a bit for each connected side: north, east, south, west.
+/// Each crack stage adds another row below.
+pub fn wall_texture_index(tile: WallTile, neighbour: impl Fn(IVec2) -> WallTile) -> Option<u32> {
+    let connects = |offset| u32::from(!matches!(neighbour(offset), WallTile::None));
+    let sides = connects(IVec2::Y)
+        | connects(IVec2::X) << 1
+        | connects(IVec2::NEG_Y) << 2
+        | connects(IVec2::NEG_X) << 3;
+
+    match tile {
+        WallTile::None => None,
+        WallTile::Wall => Some(sides),
+        WallTile::Door | WallTile::Doorway => Some(WALL_PER_ROW + sides),
+    }
+}
+
+pub fn tile_entity(
+    chunk_manager: &ChunkManager,
+    storages: &Query<&TileStorage>,
+    tile: IVec2,
+    tilemap: impl Fn(&Chunk) -> Entity,
+) -> Option<Entity> {
+    let cpos = tile_to_cpos(tile);
+    let chunk = chunk_manager.chunks.get(&cpos)?;
+    let storage = storages.get(tilemap(chunk)).ok()?;
+    let tile = tile - cpos * CHUNK_SIZE as i32;
+    storage.get(&TilePos::new(tile.x as u32, tile.y as u32))
+}
+
+/// Skips tiles next to unloaded chunks, which keep their generated textures until it loads
+fn changed_tiles(
+    chunk_manager: &ChunkManager,
+    changed: &Query<(), Changed<TileStorage>>,
+    tilemap: impl Fn(&Chunk) -> Entity,
+) -> Vec<IVec2> {
+    let loaded = |tile: IVec2| {
+        (-1..=1).all(|y| {
+            (-1..=1).all(|x| {
+                let cpos = tile_to_cpos(tile + IVec2::new(x, y));
+                chunk_manager.chunks.contains_key(&cpos)
+            })
+        })
+    };
+
+    let mut tiles = Vec::new();
+    for (&cpos, chunk) in &chunk_manager.chunks {
+        if !changed.contains(tilemap(chunk)) {
+            continue;
+        }
+
+        let min = cpos * CHUNK_SIZE as i32 - 1;
+        let max = (cpos + 1) * CHUNK_SIZE as i32;
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let tile = IVec2::new(x, y);
+                if loaded(tile) {
+                    tiles.push(tile);
+                }
+            }
+        }
+    }
+
+    tiles
+}
+
+pub fn autotile_floors(
+    chunk_manager: Res<ChunkManager>,
+    changed: Query<(), Changed<TileStorage>>,
+    storages: Query<&TileStorage>,
+    floors: Query<&FloorTile>,
+    mut tiles: Query<(&mut TileTextureIndex, &mut TileColor)>,
+    seed: Res<WorldSeed>,
+) {
+    let field = RandomField(**seed);
+    let floor_entity = |tile| tile_entity(&chunk_manager, &storages, tile, |chunk| chunk.floor);
+    let floor = |tile| {
+        floor_entity(tile)
+            .and_then(|entity| floors.get(entity).ok().copied())
+            .unwrap_or_default()
+    };
+
+    for tile in changed_tiles(&chunk_manager, &changed, |chunk| chunk.floor) {
+        let Some(entity) = floor_entity(tile) else { continue };
+        let Ok((mut texture_index, mut color)) = tiles.get_mut(entity) else { continue };
+
+        let floor_tile = floor(tile);
+        let index = floor_texture_index(floor_tile, tile, field, |offset| floor(tile + offset));
+        if texture_index.0 != index {
+            texture_index.0 = index;
+        }
+
+        let tint = floor_tint(floor_tile);
+        if color.0 != tint {
+            color.0 = tint;
+        }
+    }
+}
+
+/// Also joins walls up across chunk borders
+pub fn autotile_walls(
+    chunk_manager: Res<ChunkManager>,
+    changed: Query<(), Changed<TileStorage>>,
+    storages: Query<&TileStorage>,
+    walls: Query<&WallTile>,
+    healths: Query<&WallHealth>,
+    mut indices: Query<&mut TileTextureIndex>,
+) {
+    let wall_entity = |tile| tile_entity(&chunk_manager, &storages, tile, |chunk| chunk.walls);
+    let wall = |tile| {
+        wall_entity(tile)
+            .and_then(|entity| walls.get(entity).ok().copied())
+            .unwrap_or_default()
+    };
+
+    for tile in changed_tiles(&chunk_manager, &changed, |chunk| chunk.walls) {
+        let Some(entity) = wall_entity(tile) else { continue };
+        let Ok(mut texture_index) = indices.get_mut(entity) else { continue };
+
+        let neighbour = |offset| wall(tile + offset);
+        let Some(index) = wall_texture_index(wall(tile), neighbour) else { continue };
+        let index = match healths.get(entity) {
+            Ok(&health) => index + health.crack_stage(wall(tile)) * WALL_PER_ROW * 2,
+            Err(_) => index,
+        };
+        if texture_index.0 != index {
+            texture_index.0 = index;
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use serde::Deserialize;
+use vek::*;
+
+use crate::entities::Enemy;
+
+use super::{
+    gen::{FloorTile, RandomField, StructureGen},
+    TILE_SIZE,
+};
+
+const BIOMES_SEED: u32 = 1003;
+/// Tiles around the spawn point that are always wasteland
+const SPAWN_RADIUS: i32 = 48;
+/// City blocks are this many tiles across, including the road
+const CITY_BLOCK: i32 = 24;
+const ROAD_WIDTH: i32 = 3;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum Biome {
+    #[default]
+    Wasteland,
+    Crater,
+    City,
+    Forest,
+    Swamp,
+}
+
+impl Biome {
+    const ALL: [Biome; 5] = [
+        Biome::Wasteland,
+        Biome::Crater,
+        Biome::City,
+        Biome::Forest,
+        Biome::Swamp,
+    ];
+
+    pub fn at(wpos: bevy::prelude::Vec2, seed: u32) -> Self {
+        let tile = (wpos / TILE_SIZE).round().as_ivec2();
+        BiomeGen::new(seed).get(Vec2::new(tile.x, tile.y))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Biome::Wasteland => "Wasteland",
+            Biome::Crater => "Crater",
+            Biome::City => "Ruined City",
+            Biome::Forest => "Forest Remnants",
+            Biome::Swamp => "Toxic Swamp",
+        }
+    }
+
+    /// Other than lakes and structures
+    pub fn floor(self, pos: Vec2<i32>) -> FloorTile {
+        match self {
+            Biome::Wasteland => FloorTile::Ground,
+            Biome::Crater => FloorTile::Scorched,
+            Biome::City => {
+                if pos.x.rem_euclid(CITY_BLOCK) < ROAD_WIDTH
+                    || pos.y.rem_euclid(CITY_BLOCK) < ROAD_WIDTH
+                {
+                    FloorTile::Asphalt
+                } else {
+                    FloorTile::Ground
+                }
+            }
+            Biome::Forest => FloorTile::Grass,
+            Biome::Swamp => FloorTile::Sludge,
+        }
+    }
+
+    pub fn lake_chance(self) -> f32 {
+        match self {
+            Biome::Wasteland => 0.8,
+            Biome::Crater => 0.6,
+            Biome::City => 0.2,
+            Biome::Forest => 0.6,
+            Biome::Swamp => 1.,
+        }
+    }
+
+    pub fn structure_chance(self) -> f32 {
+        match self {
+            Biome::Wasteland => 0.8,
+            Biome::Crater => 0.3,
+            Biome::City => 1.,
+            Biome::Forest => 0.4,
+            Biome::Swamp => 0.3,
+        }
+    }
+
+    /// Chance for each additional slime to spawn in a lake
+    pub fn slime_chance(self) -> f32 {
+        match self {
+            Biome::Swamp => 0.9,
+            _ => 0.8,
+        }
+    }
+
+    /// Chance for each additional enemy to spawn in a structure
+    pub fn guard_chance(self) -> f32 {
+        match self {
+            Biome::Wasteland => 0.,
+            Biome::Crater => 0.3,
+            Biome::City => 0.5,
+            Biome::Forest => 0.2,
+            Biome::Swamp => 0.3,
+        }
+    }
+
+    pub fn guards(self) -> &'static [(Enemy, u32)] {
+        match self {
+            Biome::Wasteland | Biome::Forest | Biome::Swamp => &[(Enemy::Slime, 1)],
+            Biome::Crater => &[(Enemy::Rustache, 1)],
+            Biome::City => &[(Enemy::Rustache, 2), (Enemy::Slime, 1)],
+        }
+    }
+
+    /// Loot table for structures that don't name their own
+    pub fn loot_table(self) -> &'static str {
+        match self {
+            Biome::Wasteland => "wasteland",
+            Biome::Crater => "crater",
+            Biome::City => "city",
+            Biome::Forest => "forest",
+            Biome::Swamp => "swamp",
+        }
+    }
+
+    /// Per second, anywhere in the biome
+    pub fn radiation(self) -> f32 {
+        match self {
+            Biome::Wasteland | Biome::Forest => 0.,
+            Biome::Crater => 0.02,
+            Biome::City => 0.004,
+            Biome::Swamp => 0.008,
+        }
+    }
+}
+
+pub fn pick<T: Copy>(field: RandomField, pos: Vec2<i32>, table: &[(T, u32)]) -> T {
+    let total = table.iter().map(|&(_, weight)| weight).sum::<u32>();
+    let mut roll = field.gen_range(pos, 0..=total - 1);
+
+    for &(choice, weight) in table {
+        if roll < weight {
+            return choice;
+        }
+        roll -= weight;
+    }
+
+    unreachable!("Roll is less than the total weight")
+}
+
+/// Splits the world into Voronoi cells, each with a random biome
+pub struct BiomeGen(StructureGen);
+
+impl BiomeGen {
+    pub fn new(seed: u32) -> Self {
+        Self(StructureGen::new(seed.wrapping_add(BIOMES_SEED), 128, 64))
+    }
+
+    pub fn get(&self, pos: Vec2<i32>) -> Biome {
+        if pos.magnitude_squared() < SPAWN_RADIUS * SPAWN_RADIUS {
+            return Biome::Wasteland;
+        }
+
+        let cell = self
+            .0
+            .get(pos)
+            .into_iter()
+            .min_by_key(|cell| cell.pos.distance_squared(pos))
+            .expect("`StructureGen::get` returns 9 cells");
+
+        let index = RandomField(cell.seed).gen_range(Vec2::zero(), 0..=Biome::ALL.len() as u32 - 1);
+        Biome::ALL[index as usize]
+    }
+}
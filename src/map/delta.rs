@@ -0,0 +1,170 @@
+use bevy::utils::{HashMap, HashSet};
+
+use crate::{asset::GameAssets, construct::Construct, item::Item, prelude::*};
+
+use super::{
+    as_object_vec3,
+    gen::{ChunkData, FloorTile, WallTile},
+    tile_to_cpos, wpos_to_cpos, InChunk, UnloadPolicy, TILE_SIZE,
+};
+
+/// Something on the ground that should outlive its chunk
+#[derive(Clone, Copy)]
+pub enum Object {
+    Item(Item),
+    Construct(Construct),
+}
+
+impl Object {
+    pub fn from_components(item: Option<&Item>, construct: Option<&Construct>) -> Option<Self> {
+        item.map(|&item| Object::Item(item))
+            .or_else(|| construct.map(|&construct| Object::Construct(construct)))
+    }
+
+    pub fn spawn(self, pos: Vec2, commands: &mut Commands, assets: &GameAssets) -> Entity {
+        match self {
+            Object::Item(item) => commands
+                .spawn((
+                    SpriteBundle {
+                        texture: assets.items[item].clone(),
+                        transform: Transform::from_translation(as_object_vec3(pos)),
+                        ..default()
+                    },
+                    item,
+                ))
+                .id(),
+            Object::Construct(construct) => construct.bundle(pos, assets).spawn(commands).id(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+enum ObjectId {
+    /// Index into `ChunkData::items`
+    GenItem(usize),
+    /// Index into `ChunkData::constructs`
+    GenConstruct(usize),
+    Placed(u32),
+}
+
+/// Links an entity to its record in `ChunkDeltas`
+#[derive(Clone, Component, Copy)]
+pub struct ChunkObject {
+    cpos: IVec2,
+    id: ObjectId,
+}
+
+impl ChunkObject {
+    pub fn bundle(self) -> (Self, InChunk, UnloadPolicy) {
+        (self, InChunk(self.cpos), UnloadPolicy::Serialize)
+    }
+}
+
+#[derive(Default)]
+struct ChunkDelta {
+    removed: HashSet<ObjectId>,
+    placed: HashMap<u32, (Vec2, Object)>,
+    next_id: u32,
+    floor: HashMap<IVec2, FloorTile>,
+    walls: HashMap<IVec2, WallTile>,
+}
+
+/// Edits to each chunk since it was generated, so they survive regenerating it
+#[derive(Default, Resource)]
+pub struct ChunkDeltas(HashMap<IVec2, ChunkDelta>);
+
+impl ChunkDeltas {
+    /// Insert the result on the object's entity
+    pub fn place(&mut self, pos: Vec2, object: Object) -> ChunkObject {
+        let cpos = wpos_to_cpos(pos);
+        let delta = self.0.entry(cpos).or_default();
+        let id = delta.next_id;
+        delta.next_id += 1;
+        delta.placed.insert(id, (pos, object));
+
+        ChunkObject {
+            cpos,
+            id: ObjectId::Placed(id),
+        }
+    }
+
+    pub fn remove(&mut self, object: ChunkObject) {
+        let delta = self.0.entry(object.cpos).or_default();
+        match object.id {
+            ObjectId::Placed(id) => {
+                delta.placed.remove(&id);
+            }
+            id => {
+                delta.removed.insert(id);
+            }
+        }
+    }
+
+    pub fn set_floor(&mut self, tile: IVec2, floor: FloorTile) {
+        self.0
+            .entry(tile_to_cpos(tile))
+            .or_default()
+            .floor
+            .insert(tile, floor);
+    }
+
+    pub fn set_wall(&mut self, tile: IVec2, wall: WallTile) {
+        self.0
+            .entry(tile_to_cpos(tile))
+            .or_default()
+            .walls
+            .insert(tile, wall);
+    }
+
+    /// Includes edits in neighboring chunks, since the chunk data has a border
+    pub fn apply_tiles(&self, cpos: IVec2, chunk: &mut ChunkData) {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let Some(delta) = self.0.get(&(cpos + IVec2::new(x, y))) else { continue };
+
+                for (&tile, &floor) in &delta.floor {
+                    chunk.set_floor(vek::Vec2::new(tile.x, tile.y), floor);
+                }
+
+                for (&tile, &wall) in &delta.walls {
+                    chunk.set_wall(vek::Vec2::new(tile.x, tile.y), wall);
+                }
+            }
+        }
+    }
+
+    pub fn objects(&self, cpos: IVec2, chunk: &ChunkData) -> Vec<(ChunkObject, Vec2, Object)> {
+        let delta = self.0.get(&cpos);
+        let mut objects = Vec::new();
+
+        let items = chunk
+            .items
+            .iter()
+            .enumerate()
+            .map(|(i, &(pos, item))| (ObjectId::GenItem(i), pos, Object::Item(item)));
+        let constructs = chunk
+            .constructs
+            .iter()
+            .enumerate()
+            .map(|(i, &(pos, construct))| {
+                (ObjectId::GenConstruct(i), pos, Object::Construct(construct))
+            });
+
+        for (id, pos, object) in items.chain(constructs) {
+            if delta.is_some_and(|delta| delta.removed.contains(&id)) {
+                continue;
+            }
+
+            objects.push((ChunkObject { cpos, id }, pos * TILE_SIZE, object));
+        }
+
+        if let Some(delta) = delta {
+            for (&id, &(pos, object)) in &delta.placed {
+                let id = ObjectId::Placed(id);
+                objects.push((ChunkObject { cpos, id }, pos, object));
+            }
+        }
+
+        objects
+    }
+}
@@ -0,0 +1,241 @@
+use std::{fs::File, io::BufWriter};
+
+use crate::{
+    asset::RonAsset, construct::Construct, entities::Enemy, item::Item, loot::LootTables,
+    prelude::*, seed::WorldSeed,
+};
+
+use super::{
+    gen::{self, FloorTile, WallTile},
+    StructureTemplates, CHUNK_SIZE,
+};
+
+/// Read directly, since there's no asset server
+const STRUCTURES_PATH: &str = "assets/gen/base.structures.ron";
+const LOOT_PATH: &str = "assets/gen/base.loot.ron";
+
+/// Pixels per tile in the PNG
+const PNG_SCALE: u32 = 4;
+
+#[derive(Clone, Copy)]
+enum Cell {
+    Floor(FloorTile),
+    Wall,
+    Door,
+    Lake,
+    Item(Item),
+    Construct(Construct),
+    Enemy(Enemy),
+}
+
+impl Cell {
+    fn char(self) -> char {
+        match self {
+            Cell::Floor(FloorTile::Ground) => '.',
+            Cell::Floor(FloorTile::Water) => '~',
+            Cell::Floor(FloorTile::Concrete) => ',',
+            Cell::Floor(FloorTile::Scorched) => ':',
+            Cell::Floor(FloorTile::Asphalt) => '=',
+            Cell::Floor(FloorTile::Grass) => '*',
+            Cell::Floor(FloorTile::Sludge) => ';',
+            Cell::Wall => '#',
+            Cell::Door => '+',
+            Cell::Lake => 'O',
+            Cell::Item(item) => match item {
+                Item::Circuit => 'c',
+                Item::Metal => 'm',
+                Item::CannedFood => 'f',
+                Item::Plant => 'p',
+                Item::FuelTank => 'u',
+                Item::Generator => 'g',
+                Item::Assembler => 'a',
+                Item::Turret => 't',
+                Item::Wall => 'w',
+                Item::Door => 'd',
+            },
+            Cell::Construct(construct) => match construct {
+                Construct::Generator => 'G',
+                Construct::Assembler => 'A',
+                Construct::Turret => 'T',
+            },
+            Cell::Enemy(Enemy::Slime) => 'S',
+            Cell::Enemy(Enemy::Rustache) => 'R',
+        }
+    }
+
+    fn color(self) -> [u8; 3] {
+        match self {
+            Cell::Floor(FloorTile::Ground) => [96, 80, 56],
+            Cell::Floor(FloorTile::Water) => [40, 90, 160],
+            Cell::Floor(FloorTile::Concrete) => [128, 128, 128],
+            Cell::Floor(FloorTile::Scorched) => [60, 40, 30],
+            Cell::Floor(FloorTile::Asphalt) => [70, 70, 80],
+            Cell::Floor(FloorTile::Grass) => [70, 120, 50],
+            Cell::Floor(FloorTile::Sludge) => [90, 100, 40],
+            Cell::Wall => [32, 32, 32],
+            Cell::Door => [110, 78, 50],
+            Cell::Lake => [0, 40, 255],
+            Cell::Item(_) => [255, 220, 0],
+            Cell::Construct(_) => [0, 220, 220],
+            Cell::Enemy(Enemy::Slime) => [60, 220, 60],
+            Cell::Enemy(Enemy::Rustache) => [220, 40, 40],
+        }
+    }
+}
+
+const LEGEND: &str = "\
+.  ground        ~  water         ,  concrete      #  wall
+:  scorched      =  asphalt       *  grass         ;  sludge
++  door          O  lake center
+c  circuit       m  metal         f  canned food   p  plant
+u  fuel tank     g  generator     a  assembler     t  turret
+w  wall          d  door
+G  generator     A  assembler     T  turret        (constructs)
+S  slime         R  rustache";
+
+fn parse_ivec2(arg: &str) -> Option<IVec2> {
+    let (x, y) = arg.split_once(',')?;
+    Some(IVec2::new(x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+fn load<T: RonAsset>(path: &str) -> T {
+    std::fs::read_to_string(path)
+        .map_err(Into::into)
+        .and_then(|ron| T::from_ron(&ron))
+        .unwrap_or_else(|err| panic!("Couldn't load `{path}`: {err}"))
+}
+
+/// `dump-world [--seed <seed>] [--min <x>,<y>] [--max <x>,<y>] [--out <path>]`
+pub fn dump_world() {
+    let mut min = IVec2::new(-2, -2);
+    let mut max = IVec2::new(1, 1);
+    let mut out = String::from("world");
+
+    let mut args = std::env::args().skip(2);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--min" => {
+                min = args
+                    .next()
+                    .as_deref()
+                    .and_then(parse_ivec2)
+                    .expect("`--min` expects `<x>,<y>`");
+            }
+            "--max" => {
+                max = args
+                    .next()
+                    .as_deref()
+                    .and_then(parse_ivec2)
+                    .expect("`--max` expects `<x>,<y>`");
+            }
+            "--out" => out = args.next().expect("`--out` expects a path"),
+            // Handled by `WorldSeed`
+            "--seed" => {
+                args.next();
+            }
+            arg if arg.starts_with("--seed=") => (),
+            _ => panic!("Unexpected argument `{arg}`"),
+        }
+    }
+
+    assert!(
+        min.cmple(max).all(),
+        "`--min` must not be greater than `--max`"
+    );
+
+    let seed = WorldSeed::from_args_or_config();
+    let templates = load::<StructureTemplates>(STRUCTURES_PATH);
+    let loot = load::<LootTables>(LOOT_PATH);
+    let size = ((max - min + 1) * CHUNK_SIZE as i32).as_uvec2();
+    let origin = min * CHUNK_SIZE as i32;
+    let mut cells = vec![Cell::Floor(FloorTile::Ground); (size.x * size.y) as usize];
+
+    // Row 0 is the top of the map
+    let index = |tile: IVec2| {
+        let tile = tile - origin;
+        (tile.x >= 0 && tile.y >= 0 && tile.x < size.x as i32 && tile.y < size.y as i32)
+            .then(|| ((size.y as i32 - 1 - tile.y) * size.x as i32 + tile.x) as usize)
+    };
+    let tile = |pos: Vec2| pos.round().as_ivec2();
+
+    for cy in min.y..=max.y {
+        for cx in min.x..=max.x {
+            let chunk = gen::gen_chunk(IVec2::new(cx, cy), *seed, &templates, &loot);
+            let bounds = chunk.chunk_aabr();
+
+            for y in bounds.min.y..=bounds.max.y {
+                for x in bounds.min.x..=bounds.max.x {
+                    let Some(i) = index(IVec2::new(x, y)) else { continue };
+                    cells[i] = match chunk.get_wall_tile(x, y) {
+                        WallTile::None => Cell::Floor(chunk.get_floor_tile(x, y)),
+                        WallTile::Wall => Cell::Wall,
+                        WallTile::Door | WallTile::Doorway => Cell::Door,
+                    };
+                }
+            }
+
+            // Later overlays win
+            let overlays = chunk
+                .lakes
+                .iter()
+                .map(|&(pos, _)| (pos, Cell::Lake))
+                .chain(
+                    chunk
+                        .items
+                        .iter()
+                        .map(|&(pos, item)| (pos, Cell::Item(item))),
+                )
+                .chain(
+                    chunk
+                        .constructs
+                        .iter()
+                        .map(|&(pos, construct)| (pos, Cell::Construct(construct))),
+                )
+                .chain(
+                    chunk
+                        .enemies
+                        .iter()
+                        .map(|&(pos, enemy)| (pos, Cell::Enemy(enemy))),
+                );
+
+            for (pos, cell) in overlays {
+                if let Some(i) = index(tile(pos)) {
+                    cells[i] = cell;
+                }
+            }
+        }
+    }
+
+    let ascii = cells
+        .chunks(size.x as usize)
+        .map(|row| row.iter().map(|cell| cell.char()).collect::<String>() + "\n")
+        .collect::<String>();
+    std::fs::write(format!("{out}.txt"), ascii).expect("Couldn't write the ASCII map");
+
+    let file = File::create(format!("{out}.png")).expect("Couldn't create the PNG");
+    let mut encoder =
+        png::Encoder::new(BufWriter::new(file), size.x * PNG_SCALE, size.y * PNG_SCALE);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut pixels = Vec::with_capacity((size.x * size.y * PNG_SCALE * PNG_SCALE * 3) as usize);
+    for row in cells.chunks(size.x as usize) {
+        for _ in 0..PNG_SCALE {
+            for cell in row {
+                for _ in 0..PNG_SCALE {
+                    pixels.extend(cell.color());
+                }
+            }
+        }
+    }
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .expect("Couldn't write the PNG");
+
+    println!(
+        "Wrote chunks {min} to {max} of seed {} to {out}.txt and {out}.png\n\n{LEGEND}",
+        *seed
+    );
+}
@@ -1,13 +1,23 @@
 use std::ops::RangeInclusive;
 
-use crate::{construct::Construct, entities::Enemy, item::Item};
+use bevy::prelude::Component;
 
-use super::CHUNK_SIZE;
+use crate::{
+    construct::Construct, entities::Enemy, item::Item, loot::LootTables, physics::TilePhysics,
+};
+
+use super::{
+    biome::{self, BiomeGen},
+    template::{StructureTemplates, TemplateTile},
+    CHUNK_SIZE,
+};
 
 use vek::*;
 
-const ITEM_CHANCE: f32 = 0.8;
-const ASSEMBLER_CHANCE: f32 = 0.5;
+/// Default chance for a structure's loot tile to have an item
+pub const ITEM_CHANCE: f32 = 0.8;
+/// Default chance for a structure's construct tile to have its construct
+pub const ASSEMBLER_CHANCE: f32 = 0.5;
 
 #[derive(Clone, Copy)]
 pub struct RandomField(pub u32);
@@ -55,8 +65,8 @@ impl RandomField {
 
 #[derive(Default, Clone, Copy)]
 pub struct StructureField {
-    pos: Vec2<i32>,
-    seed: u32,
+    pub pos: Vec2<i32>,
+    pub seed: u32,
 }
 
 #[derive(Clone)]
@@ -194,19 +204,65 @@ impl StructureGen {
     }
 }
 
-#[derive(Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Default, Clone, Copy, Component, PartialEq, Eq)]
 pub enum FloorTile {
     #[default]
     Ground,
     Water,
     Concrete,
+    Scorched,
+    Asphalt,
+    Grass,
+    Sludge,
 }
 
-#[derive(Default, Clone, Copy)]
+impl FloorTile {
+    /// Lake water is slow, slippery and irradiated, and roads are fast
+    pub fn physics(self) -> TilePhysics {
+        let (speed, friction, radiation) = match self {
+            FloorTile::Ground | FloorTile::Concrete | FloorTile::Grass => (1., 20., 0.),
+            FloorTile::Water => (0.5, 4., 0.05),
+            FloorTile::Scorched => (1., 20., 0.01),
+            FloorTile::Asphalt => (1.3, 20., 0.),
+            FloorTile::Sludge => (0.7, 8., 0.02),
+        };
+
+        TilePhysics {
+            speed,
+            friction,
+            radiation,
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Component)]
 pub enum WallTile {
     #[default]
     None,
     Wall,
+    /// Framed by the walls on either side
+    Door,
+    /// The empty frame of a generated structure's door. Anything can go through.
+    Doorway,
+}
+
+impl WallTile {
+    /// Doors only let friendly entities through
+    pub fn blocks(self, friendly: bool) -> bool {
+        match self {
+            WallTile::None | WallTile::Doorway => false,
+            WallTile::Wall => true,
+            WallTile::Door => !friendly,
+        }
+    }
+
+    pub fn max_health(self) -> f32 {
+        match self {
+            WallTile::None => 0.,
+            WallTile::Wall => 20.,
+            WallTile::Door | WallTile::Doorway => 10.,
+        }
+    }
 }
 
 const GEN_SIZE: u32 = CHUNK_SIZE + 2;
@@ -242,27 +298,12 @@ impl ChunkData {
         }
     }
     #[inline]
-    fn set_floor(&mut self, p: Vec2<i32>, t: FloorTile) {
+    pub fn set_floor(&mut self, p: Vec2<i32>, t: FloorTile) {
         if let Some(index) = self.index_wpos(p) {
             self.floor[index] = t;
         }
     }
 
-    /// Inclusive
-    fn blit_floor(&mut self, min: Vec2<i32>, max: Vec2<i32>, t: FloorTile) {
-        let aabr = self.gen_aabr();
-        let min = (min - aabr.min).map(|e| e.max(0));
-        let max = (max - aabr.min).map2(Vec2::from(aabr.size()), |a, b| a.min(b));
-
-        if min.x <= max.x && min.y <= max.y {
-            for y in min.y..=max.y {
-                for x in min.x..=max.x {
-                    self.floor[Self::index_rpos(Vec2::new(x, y))] = t;
-                }
-            }
-        }
-    }
-
     fn draw_line(&self, start: Vec2<i32>, end: Vec2<i32>, mut set: impl FnMut(usize)) {
         let aabr = self.gen_aabr();
         if start.x == end.x {
@@ -299,15 +340,8 @@ impl ChunkData {
         self.floor = floor;
     }
 
-    /// Axis aligned line, Inclusive
-    fn wall_line(&mut self, start: Vec2<i32>, end: Vec2<i32>, t: WallTile) {
-        let mut walls = std::mem::take(&mut self.walls);
-        self.draw_line(start, end, |i| walls[i] = t);
-        self.walls = walls;
-    }
-
     #[inline]
-    fn set_wall(&mut self, p: Vec2<i32>, t: WallTile) {
+    pub fn set_wall(&mut self, p: Vec2<i32>, t: WallTile) {
         if let Some(index) = self.index_wpos(p) {
             self.walls[index] = t;
         }
@@ -338,8 +372,11 @@ impl ChunkData {
     }
 
     fn lake(&mut self, center: Vec2<f32>, radius: f32) {
-        self.lakes
-            .push((bevy::prelude::Vec2::from_array(center.into_array()), radius));
+        let aabr = self.chunk_aabr();
+        if aabr.contains_point(center.as_()) {
+            self.lakes
+                .push((bevy::prelude::Vec2::from_array(center.into_array()), radius));
+        }
     }
 
     /// The area of tiles we generate for this chunk.
@@ -389,13 +426,26 @@ impl Default for ChunkData {
 
 const LAKES_SEED: u32 = 1001;
 const STRUCTURES_SEED: u32 = 1002;
-const ENEMY_CHANCE: f32 = 0.8;
 
-pub fn gen_chunk(cpos: bevy::prelude::IVec2, seed: u32) -> ChunkData {
+pub fn gen_chunk(
+    cpos: bevy::prelude::IVec2,
+    seed: u32,
+    templates: &StructureTemplates,
+    loot: &LootTables,
+) -> ChunkData {
     let cpos = Vec2::from(cpos.to_array());
 
     let mut chunk = ChunkData::new(cpos);
     let chunk_aabr = chunk.gen_aabr();
+    let biomes = BiomeGen::new(seed);
+
+    for y in chunk_aabr.min.y..=chunk_aabr.max.y {
+        for x in chunk_aabr.min.x..=chunk_aabr.max.x {
+            let p = Vec2::new(x, y);
+            chunk.set_floor(p, biomes.get(p).floor(p));
+        }
+    }
+
     let lakes = StructureGen::new(seed.wrapping_add(LAKES_SEED), 50, 24);
 
     struct Lake {
@@ -410,16 +460,25 @@ pub fn gen_chunk(cpos: bevy::prelude::IVec2, seed: u32) -> ChunkData {
 
     let lakes = lakes
         .iter_area(structure_query_area)
+        .filter(|structure| {
+            RandomField(structure.seed)
+                .chance(Vec2::new(-1, -1), biomes.get(structure.pos).lake_chance())
+        })
         .map(|structure| Lake {
             bounds: RandomField(structure.seed).gen_bounds(structure.pos, 10..=24),
             seed: structure.seed,
         })
         .collect::<Vec<_>>();
 
-    for lake in lakes
-        .iter()
-        .filter(|lake| lake.bounds.intersection(chunk_aabr).is_valid())
-    {
+    // Rows are jittered, so they can stick out of the bounds by a tile
+    for lake in lakes.iter().filter(|lake| {
+        Aabr {
+            min: lake.bounds.min - 1,
+            max: lake.bounds.max + 1,
+        }
+        .intersection(chunk_aabr)
+        .is_valid()
+    }) {
         let b = lake.bounds.as_::<f32>();
         let field = RandomField(lake.seed);
         for y in lake.bounds.min.y..=lake.bounds.max.y {
@@ -435,8 +494,9 @@ pub fn gen_chunk(cpos: bevy::prelude::IVec2, seed: u32) -> ChunkData {
             chunk.floor_line(start, end, FloorTile::Water);
         }
 
+        let slime_chance = biomes.get(lake.bounds.center()).slime_chance();
         let mut i = 1;
-        while field.chance(Vec2::new(i, 0), ENEMY_CHANCE) {
+        while field.chance(Vec2::new(i, 0), slime_chance) {
             i += 1;
 
             let p = Vec2::new(1, -1).map(|i| field.gen_f32(Vec2::new((1 + i) * i, 0)))
@@ -454,7 +514,30 @@ pub fn gen_chunk(cpos: bevy::prelude::IVec2, seed: u32) -> ChunkData {
     let structures = StructureGen::new(seed.wrapping_add(STRUCTURES_SEED), 40, 20);
 
     for structure in structures.iter_area(structure_query_area) {
-        let bounds = RandomField(structure.seed).gen_bounds(structure.pos, 8..=15);
+        let biome = biomes.get(structure.pos);
+        let field = RandomField(structure.seed);
+        if !field.chance(Vec2::new(-1, -1), biome.structure_chance()) {
+            continue;
+        }
+
+        let candidates = templates
+            .iter()
+            .filter(|template| template.generates_in(biome))
+            .map(|template| (template, template.weight))
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            continue;
+        }
+
+        let template = biome::pick(field, Vec2::new(-2, -2), &candidates);
+        let rotation = field.gen_range(Vec2::new(-3, -3), 0..=3);
+        let size = Vec2::from(template.size(rotation));
+        let min = structure.pos - size / 2;
+        let bounds = Aabr {
+            min,
+            max: min + size - 1,
+        };
+
         if !bounds.intersection(chunk_aabr).is_valid()
             || lakes
                 .iter()
@@ -463,58 +546,48 @@ pub fn gen_chunk(cpos: bevy::prelude::IVec2, seed: u32) -> ChunkData {
             continue;
         }
 
-        let field = RandomField(structure.seed);
-
-        let min_max = Vec2::new(bounds.min.x, bounds.max.y);
-        let max_min = Vec2::new(bounds.max.x, bounds.min.y);
-
-        chunk.blit_floor(bounds.min, bounds.max, FloorTile::Concrete);
-        chunk.wall_line(bounds.min, min_max, WallTile::Wall);
-        chunk.wall_line(bounds.min, max_min, WallTile::Wall);
-        chunk.wall_line(bounds.max, min_max, WallTile::Wall);
-        chunk.wall_line(bounds.max, max_min, WallTile::Wall);
+        let loot_table = loot.get(template.loot.as_deref().unwrap_or(biome.loot_table()));
 
-        let door = ([
-            Vec2::new(bounds.min.x, bounds.center().y),
-            Vec2::new(bounds.max.x, bounds.center().y),
-            Vec2::new(bounds.center().x, bounds.min.y),
-            Vec2::new(bounds.center().x, bounds.max.y),
-        ])[field.gen_range(structure.pos, 0..=3) as usize];
+        for (offset, tile) in template.tiles(rotation) {
+            let p = min + offset;
+            // Separate from `field`, so picks don't line up with chances
+            let pick_field = RandomField(field.gen(p));
 
-        chunk.set_wall(door, WallTile::None);
-
-        let mut i = 1;
-        let b = bounds.as_::<f32>();
-        while field.chance(Vec2::new(i, 0), ITEM_CHANCE) {
-            i += 1;
-
-            let item: Item = [
-                Item::Circuit,
-                Item::Circuit,
-                Item::Circuit,
-                Item::Metal,
-                Item::Metal,
-                Item::Metal,
-                Item::CannedFood,
-                Item::CannedFood,
-                Item::FuelTank,
-                Item::FuelTank,
-            ][field.gen_range(Vec2::new(0, i), 0..=9) as usize];
-
-            let p = Vec2::new(1, -1).map(|a| field.gen_f32(Vec2::new((1 + i) * a, 0)))
-                * (b.max - b.min - 2.0)
-                + b.min
-                + 1.0;
-            chunk.item(p, item);
-        }
+            match tile {
+                TemplateTile::Empty => continue,
+                TemplateTile::Wall => {
+                    chunk.set_floor(p, FloorTile::Concrete);
+                    chunk.set_wall(p, WallTile::Wall);
+                    continue;
+                }
+                TemplateTile::Door => {
+                    chunk.set_floor(p, FloorTile::Concrete);
+                    chunk.set_wall(p, WallTile::Doorway);
+                    continue;
+                }
+                TemplateTile::Floor => (),
+                TemplateTile::Loot => {
+                    if let Some(item) = loot_table
+                        .filter(|_| field.chance(p, template.loot_chance))
+                        .and_then(|table| biome::pick(pick_field, p, table.entries()))
+                    {
+                        chunk.item(p.as_(), item);
+                    }
+                }
+                TemplateTile::Construct(construct) => {
+                    if field.chance(p, template.construct_chance) {
+                        chunk.construct(p.as_(), construct);
+                    }
+                }
+                TemplateTile::Guard => {
+                    if field.chance(p, biome.guard_chance()) {
+                        chunk.enemy(p.as_(), biome::pick(pick_field, p, biome.guards()));
+                    }
+                }
+            }
 
-        if field.chance(Vec2::new(i + 1, 0), ASSEMBLER_CHANCE) {
-            // I don't understand this math, I'm just copying it lol
-            let p = Vec2::new(3, -3).map(|i| field.gen_f32(Vec2::new((2 + i) * i, 0)))
-                * (b.max - b.min - 2.0)
-                + b.min
-                + 1.0;
-            chunk.construct(p, Construct::Assembler);
+            chunk.set_floor(p, FloorTile::Concrete);
+            chunk.set_wall(p, WallTile::None);
         }
     }
 
@@ -531,3 +604,181 @@ pub fn gen_chunk(cpos: bevy::prelude::IVec2, seed: u32) -> ChunkData {
 
     chunk
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::IVec2;
+
+    use crate::asset::RonAsset;
+
+    use super::*;
+
+    fn templates() -> StructureTemplates {
+        StructureTemplates::from_ron(include_str!("../../assets/gen/base.structures.ron")).unwrap()
+    }
+
+    fn loot() -> LootTables {
+        LootTables::from_ron(include_str!("../../assets/gen/base.loot.ron")).unwrap()
+    }
+
+    /// FNV-1a, so the snapshots don't depend on the standard library's hasher
+    struct Fnv(u64);
+
+    impl Fnv {
+        fn write(&mut self, bytes: &[u8]) {
+            for &byte in bytes {
+                self.0 ^= byte as u64;
+                self.0 = self.0.wrapping_mul(0x100000001b3);
+            }
+        }
+
+        fn write_pos(&mut self, pos: bevy::prelude::Vec2) {
+            self.write(&pos.x.to_bits().to_le_bytes());
+            self.write(&pos.y.to_bits().to_le_bytes());
+        }
+    }
+
+    fn hash_chunk(chunk: &ChunkData) -> u64 {
+        let mut hasher = Fnv(0xcbf29ce484222325);
+
+        for &floor in &chunk.floor {
+            hasher.write(&[floor as u8]);
+        }
+
+        for &wall in &chunk.walls {
+            hasher.write(&[wall as u8]);
+        }
+
+        for &(pos, item) in &chunk.items {
+            hasher.write_pos(pos);
+            hasher.write(&[item as u8]);
+        }
+
+        for &(pos, construct) in &chunk.constructs {
+            hasher.write_pos(pos);
+            hasher.write(&[construct as u8]);
+        }
+
+        for &(pos, enemy) in &chunk.enemies {
+            hasher.write_pos(pos);
+            hasher.write(&[enemy as u8]);
+        }
+
+        for &(pos, radius) in &chunk.lakes {
+            hasher.write_pos(pos);
+            hasher.write(&radius.to_bits().to_le_bytes());
+        }
+
+        hasher.0
+    }
+
+    /// `(seed, chunk position, hash)`. If a change to generation is intended, paste in the table
+    /// `cargo test snapshots` prints when it fails.
+    const SNAPSHOTS: &[(u32, [i32; 2], u64)] = &[
+        (0, [0, 0], 0x5b2a843a4796790d),
+        (0, [1, -1], 0x4a0493d5dc51e1b9),
+        (0, [-3, 2], 0x8b28767a7659902e),
+        (0, [2, 3], 0x2657993e378317d9),
+        (0, [12, 0], 0x20de8c60ff233160),
+        (1, [0, 0], 0x62de4c923ded1cc8),
+        (1, [1, -1], 0xc9dcc9ba69a654d1),
+        (1, [-3, 2], 0x05f0fe9af9ec7b34),
+        (1, [2, 3], 0xf45db3f22246a600),
+        (1, [12, 0], 0x9bab5a66df0b8023),
+        (12345, [0, 0], 0x83175c4256beedc5),
+        (12345, [1, -1], 0xca2be00b90bcbb15),
+        (12345, [-3, 2], 0x28fd2ae5d6067f31),
+        (12345, [2, 3], 0x10da00f9475fbb91),
+        (12345, [12, 0], 0x62af860b209d8e8f),
+        (3735928559, [0, 0], 0xbba65c679a04648a),
+        (3735928559, [1, -1], 0xfcf00e0c667419f4),
+        (3735928559, [-3, 2], 0xc011f735dd229c46),
+        (3735928559, [2, 3], 0xc506eb677c548c63),
+        (3735928559, [12, 0], 0xcc0b327e0ac15101),
+    ];
+
+    #[test]
+    fn snapshots() {
+        let templates = templates();
+        let loot = loot();
+        let mut table = String::new();
+        let mut mismatches = 0;
+
+        for &(seed, cpos, expected) in SNAPSHOTS {
+            let hash = hash_chunk(&gen_chunk(IVec2::from_array(cpos), seed, &templates, &loot));
+            table += &format!("        ({seed}, {cpos:?}, {hash:#018x}),\n");
+
+            if hash != expected {
+                eprintln!("Chunk {cpos:?} of seed {seed} changed");
+                mismatches += 1;
+            }
+        }
+
+        if mismatches > 0 {
+            eprintln!("{table}");
+        }
+        assert_eq!(mismatches, 0, "World generation changed");
+    }
+
+    #[test]
+    fn chunk_borders_line_up() {
+        const SEEDS: [u32; 3] = [0, 1, 12345];
+        // Stays clear of the world border, which is only generated within each chunk
+        const RANGE: std::ops::RangeInclusive<i32> = -4..=3;
+
+        let templates = templates();
+        let loot = loot();
+        let mut checked_features = 0;
+
+        for seed in SEEDS {
+            for y in RANGE {
+                for x in RANGE {
+                    let chunk = gen_chunk(IVec2::new(x, y), seed, &templates, &loot);
+                    let gen_aabr = chunk.gen_aabr();
+
+                    for (dx, dy) in [(1, 0), (0, 1), (1, 1), (1, -1)] {
+                        let other = gen_chunk(IVec2::new(x + dx, y + dy), seed, &templates, &loot);
+                        let overlap = gen_aabr.intersection(other.gen_aabr());
+
+                        for y in overlap.min.y..=overlap.max.y {
+                            for x in overlap.min.x..=overlap.max.x {
+                                let floor = chunk.get_floor_tile(x, y);
+                                let wall = chunk.get_wall_tile(x, y);
+
+                                assert!(
+                                    floor == other.get_floor_tile(x, y),
+                                    "Floor at {x}, {y} differs between chunks (seed {seed})"
+                                );
+                                assert!(
+                                    wall as u8 == other.get_wall_tile(x, y) as u8,
+                                    "Wall at {x}, {y} differs between chunks (seed {seed})"
+                                );
+
+                                if floor != FloorTile::Ground || wall as u8 != WallTile::None as u8
+                                {
+                                    checked_features += 1;
+                                }
+                            }
+                        }
+                    }
+
+                    // Each lake belongs to one chunk, so its radiation isn't doubled
+                    for &(center, _) in &chunk.lakes {
+                        for other in [IVec2::new(x + 1, y), IVec2::new(x, y + 1)] {
+                            let other = gen_chunk(other, seed, &templates, &loot);
+                            assert!(
+                                !other.lakes.iter().any(|&(other, _)| other == center),
+                                "Lake at {center} is in more than one chunk (seed {seed})"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        assert!(
+            checked_features > 0,
+            "No structures or lakes crossed a chunk border"
+        );
+    }
+}
@@ -1,22 +1,61 @@
+mod autotile;
+mod biome;
+mod delta;
+mod dump;
 mod gen;
+mod path;
+mod raycast;
+mod sweep;
+mod template;
+mod wall;
+
+use std::{cmp::Ordering, collections::BinaryHeap};
 
 use bevy::{
-    math::{Vec3Swizzles, Vec4Swizzles},
-    utils::HashMap,
+    ecs::system::SystemState,
+    math::Vec3Swizzles,
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::{futures::now_or_never, FloatOrd, HashMap, HashSet},
 };
 
 use crate::{
-    asset::GameAssets, entities::TextureAtlases, physics::Vel, prelude::*, stats::RadiationSource,
+    asset::GameAssets, construct::Construct, entities::TextureAtlases, item::Item,
+    loot::LootTables, physics::Vel, prelude::*, seed::WorldSeed, stats::RadiationSource,
     SCREEN_SIZE,
 };
 
-use self::gen::RandomField;
+use self::{
+    autotile::{
+        autotile_floors, autotile_walls, floor_texture_index, floor_tint, wall_texture_index,
+    },
+    gen::{ChunkData, RandomField},
+    path::{track_wall_changes, WallVersion},
+};
+
+pub use biome::Biome;
+pub use delta::{ChunkDeltas, ChunkObject, Object};
+pub use dump::dump_world;
+pub use gen::{FloorTile, WallTile};
+pub use path::{Path, Pathfinder};
+pub use template::StructureTemplates;
+pub use wall::{build_wall, DamageWall, Explosion, WallHealth};
 
 pub fn map_plugin(app: &mut App) {
     app.add_plugin(TilemapPlugin)
         .init_resource::<ChunkManager>()
+        .init_resource::<ChunkDeltas>()
+        .init_resource::<WallVersion>()
+        .add_event::<DamageWall>()
+        .add_event::<Explosion>()
         .add_system(spawn_chunks_around_camera)
-        .add_system(despawn_outofrange_chunks);
+        .add_system(despawn_outofrange_chunks)
+        .add_system(update_chunk_owners)
+        .add_system(wall::explode.before(wall::damage_walls))
+        .add_system(wall::damage_walls)
+        // After edits' commands are applied
+        .add_system(autotile_floors.in_base_set(CoreSet::PostUpdate))
+        .add_system(autotile_walls.in_base_set(CoreSet::PostUpdate))
+        .add_system(track_wall_changes.in_base_set(CoreSet::PostUpdate));
 }
 
 // TODO Make this between 2 and 3
@@ -34,6 +73,14 @@ const Z_BASE_OBJECTS: f32 = 200.; // Ground object sprites.
 const FLOOR_LAYER: f32 = 0.;
 const WALL_LAYER: f32 = 1.;
 const SCALE: f32 = 1.;
+/// Chunks within this many chunks of a camera are generated
+const GEN_RADIUS: i32 = 2;
+/// Chunks further than this many chunks from every camera are unloaded
+const UNLOAD_RADIUS: i32 = 3;
+/// Max chunks being generated in the background at once
+const MAX_GENERATING: usize = 8;
+/// Spawning a chunk's tiles is done on the main thread
+const MAX_SPAWNED_PER_TICK: usize = 1;
 
 pub struct Chunk {
     pub floor: Entity,
@@ -44,245 +91,192 @@ pub struct Chunk {
 #[derive(Component)]
 struct ChunkMarker;
 
-#[derive(Default, Resource)]
-pub struct ChunkManager {
-    chunks: HashMap<IVec2, Chunk>,
-}
-pub type ChunkQueryMut<'world, 'state, 'a> = Query<
-    'world,
-    'state,
-    (
-        &'a mut TileStorage,
-        &'a TilemapSize,
-        &'a TilemapGridSize,
-        &'a TilemapType,
-        &'a Transform,
-    ),
-    Without<Vel>,
->;
-pub type ChunkQuery<'world, 'state, 'a> = Query<
-    'world,
-    'state,
-    (
-        &'a TileStorage,
-        &'a TilemapSize,
-        &'a TilemapGridSize,
-        &'a TilemapType,
-        &'a Transform,
-    ),
-    Without<Vel>,
->;
-
-pub struct TileEntryMut<'a> {
-    storage: Mut<'a, TileStorage>,
-    chunk_pos: IVec2,
-    tile_pos: TilePos,
-    entity: Entity,
+#[derive(Clone, Component, Copy, Deref, DerefMut)]
+pub struct InChunk(pub IVec2);
+
+/// What happens to an entity when its chunk unloads. Entities without one are despawned.
+#[derive(Clone, Component, Copy, Default)]
+pub enum UnloadPolicy {
+    /// Respawned from `ChunkDeltas` when the chunk loads again
+    Serialize,
+    #[default]
+    Despawn,
 }
 
-impl<'a> TileEntryMut<'a> {
-    pub fn entity(&self) -> Entity {
-        self.entity
+fn unload_entity(
+    commands: &mut Commands,
+    deltas: &mut ChunkDeltas,
+    entity: Entity,
+    policy: Option<&UnloadPolicy>,
+    pos: Vec2,
+    object: Option<&ChunkObject>,
+    kind: Option<Object>,
+) {
+    match policy.copied().unwrap_or_default() {
+        UnloadPolicy::Serialize => {
+            if let (None, Some(kind)) = (object, kind) {
+                deltas.place(pos, kind);
+            }
+        }
+        UnloadPolicy::Despawn => (),
     }
 
-    pub fn despawn(&mut self, commands: &mut Commands) {
-        self.storage.remove(&self.tile_pos);
-        commands.entity(self.entity).despawn_recursive();
-    }
+    commands.entity(entity).despawn_recursive();
 }
 
-pub struct TileEntry<'a> {
-    storage: &'a TileStorage,
-    chunk_pos: IVec2,
-    tile_pos: TilePos,
-    entity: Entity,
+#[derive(Default, Resource)]
+pub struct ChunkManager {
+    chunks: HashMap<IVec2, Chunk>,
+    /// Chunks being generated on the async compute pool
+    generating: HashMap<IVec2, Task<ChunkData>>,
+    generated: HashMap<IVec2, ChunkData>,
 }
+pub type ChunkQueryMut<'world, 'state, 'a> =
+    Query<'world, 'state, &'a mut TileStorage, Without<Vel>>;
+pub type ChunkQuery<'world, 'state, 'a> = Query<'world, 'state, &'a TileStorage, Without<Vel>>;
 
-impl<'a> TileEntry<'a> {
-    pub fn entity(&self) -> Entity {
-        self.entity
+impl ChunkManager {
+    /// Loaded, or will be soon
+    fn is_tracked(&self, cpos: IVec2) -> bool {
+        self.chunks.contains_key(&cpos)
+            || self.generating.contains_key(&cpos)
+            || self.generated.contains_key(&cpos)
     }
 
-    pub fn project_to_edge(&self, start: Vec2, end: Vec2) -> Option<Vec2> {
-        let d = end - start;
-        let tile_wpos = (self.chunk_pos.as_vec2() * CHUNK_SIZE as f32
-            + Vec2::new(self.tile_pos.x as f32, self.tile_pos.y as f32))
-            * TILE_SIZE;
-        let half_tile = Vec2::splat(TILE_SIZE * 0.5);
-
-        let l = tile_wpos - start;
-
-        let t0 = (l - half_tile) / d;
-        let t1 = (l + half_tile) / d;
-        let tmin = t0.max_element();
-        let tmax = t1.min_element();
+    fn tile_entity(
+        &self,
+        tile: IVec2,
+        chunk_query: &ChunkQuery,
+        tilemap: impl FnOnce(&Chunk) -> Entity,
+    ) -> Option<Entity> {
+        let cpos = tile_to_cpos(tile);
+        let storage = chunk_query.get(tilemap(self.chunks.get(&cpos)?)).ok()?;
+        let tile = tile - cpos * CHUNK_SIZE as i32;
+        storage.get(&TilePos::new(tile.x as u32, tile.y as u32))
+    }
 
-        if tmax < tmin || tmax < 0.0 || tmin > 1.0 {
-            return None;
-        }
+    fn wall_entity(&self, tile: IVec2, chunk_query: &ChunkQuery) -> Option<Entity> {
+        self.tile_entity(tile, chunk_query, |chunk| chunk.walls)
+    }
 
-        if tmin > 0.0 {
-            Some(start + d * tmin)
-        } else {
-            Some(end)
-        }
+    pub fn floor_entity(&self, tile: IVec2, chunk_query: &ChunkQuery) -> Option<Entity> {
+        self.tile_entity(tile, chunk_query, |chunk| chunk.floor)
     }
-}
 
-impl ChunkManager {
-    fn get_tile_mut<'a>(
+    /// Unloaded tiles don't block
+    pub fn wall_blocks(
         &self,
-        wpos: Vec2,
-        chunk_query: &'a mut ChunkQueryMut,
-        tilemap: impl FnOnce(&Chunk) -> Entity,
-    ) -> Option<TileEntryMut<'a>> {
-        let wpos = wpos - Vec2::ONE * 0.5;
-        let cpos = wpos_to_cpos(wpos);
-        self.chunks.get(&cpos).and_then(|chunk| {
-            let (storage, size, grid_size, ty, transform) =
-                chunk_query.get_mut(tilemap(chunk)).ok()?;
-            let in_map_pos: Vec2 = {
-                let pos = Vec4::from((wpos, 0.0, 1.0));
-                let in_map_pos = transform.compute_matrix().inverse() * pos;
-                in_map_pos.xy()
-            };
-            let in_map_pos = Vec2::new(
-                in_map_pos.x.rem_euclid(CHUNK_SIZE as f32 * TILE_SIZE),
-                in_map_pos.y.rem_euclid(CHUNK_SIZE as f32 * TILE_SIZE),
-            );
-
-            let tile_pos = TilePos::from_world_pos(&in_map_pos, size, grid_size, ty)?;
-            storage.get(&tile_pos).map(|entity| TileEntryMut {
-                storage,
-                chunk_pos: cpos,
-                tile_pos,
-                entity,
-            })
-        })
+        tile: IVec2,
+        chunk_query: &ChunkQuery,
+        walls: &Query<&WallTile>,
+        friendly: bool,
+    ) -> bool {
+        let Some(entity) = self.wall_entity(tile, chunk_query) else { return false };
+        walls.get(entity).map_or(true, |wall| wall.blocks(friendly))
     }
 
-    fn get_tile<'a>(
+    /// Records the edit, so it survives the chunk unloading
+    pub fn set_wall(
         &self,
         wpos: Vec2,
-        chunk_query: &'a ChunkQuery,
-        tilemap: impl FnOnce(&Chunk) -> Entity,
-    ) -> Option<TileEntry<'a>> {
-        let wpos = wpos - Vec2::ONE * 0.5;
-        let cpos = wpos_to_cpos(wpos);
-        self.chunks.get(&cpos).and_then(|chunk| {
-            let (storage, size, grid_size, ty, transform) = chunk_query.get(tilemap(chunk)).ok()?;
-            let in_map_pos: Vec2 = {
-                let pos = Vec4::from((wpos, 0.0, 1.0));
-                let in_map_pos = transform.compute_matrix().inverse() * pos;
-                in_map_pos.xy()
-            };
-            let in_map_pos = Vec2::new(
-                in_map_pos.x.rem_euclid(CHUNK_SIZE as f32 * TILE_SIZE),
-                in_map_pos.y.rem_euclid(CHUNK_SIZE as f32 * TILE_SIZE),
-            );
-
-            let tile_pos = TilePos::from_world_pos(&in_map_pos, size, grid_size, ty)?;
-            storage.get(&tile_pos).map(|entity| TileEntry {
-                storage,
-                chunk_pos: cpos,
-                tile_pos,
-                entity,
-            })
-        })
+        wall: WallTile,
+        commands: &mut Commands,
+        chunk_query: &mut ChunkQueryMut,
+        deltas: &mut ChunkDeltas,
+    ) {
+        let tile = wpos_to_tile(wpos);
+        deltas.set_wall(tile, wall);
+
+        let storage = self.wall_storage_mut(tile, chunk_query);
+        let Some((mut storage, tile_pos)) = storage else { return };
+        match (storage.get(&tile_pos), wall) {
+            (Some(entity), WallTile::None) => {
+                storage.remove(&tile_pos);
+                commands.entity(entity).despawn_recursive();
+            }
+            (Some(entity), wall) => {
+                commands
+                    .entity(entity)
+                    .insert((wall, WallHealth::new(wall)));
+                storage.set_changed();
+            }
+            (None, WallTile::None) => (),
+            (None, wall) => {
+                let tilemap_id = TilemapId(self.chunks[&tile_to_cpos(tile)].walls);
+                let entity = commands
+                    .spawn((
+                        TileBundle {
+                            position: tile_pos,
+                            tilemap_id,
+                            ..default()
+                        },
+                        wall,
+                        WallHealth::new(wall),
+                    ))
+                    .id();
+                storage.set(&tile_pos, entity);
+            }
+        }
     }
 
-    pub fn get_wall_tile_mut<'a>(
+    pub fn clear_wall(
         &self,
         wpos: Vec2,
-        chunk_query: &'a mut ChunkQueryMut,
-    ) -> Option<TileEntryMut<'a>> {
-        self.get_tile_mut(wpos, chunk_query, |chunk| chunk.walls)
+        commands: &mut Commands,
+        chunk_query: &mut ChunkQueryMut,
+        deltas: &mut ChunkDeltas,
+    ) {
+        self.set_wall(wpos, WallTile::None, commands, chunk_query, deltas);
     }
 
-    pub fn get_wall_tile<'a>(
+    /// Records the edit, so it survives the chunk unloading
+    #[allow(dead_code)]
+    pub fn set_floor(
         &self,
         wpos: Vec2,
-        chunk_query: &'a ChunkQuery,
-    ) -> Option<TileEntry<'a>> {
-        self.get_tile(wpos, chunk_query, |chunk| chunk.walls)
+        floor: FloorTile,
+        commands: &mut Commands,
+        chunk_query: &mut ChunkQueryMut,
+        deltas: &mut ChunkDeltas,
+    ) {
+        let tile = wpos_to_tile(wpos);
+        deltas.set_floor(tile, floor);
+
+        let storage = self.floor_storage_mut(tile, chunk_query);
+        let Some((mut storage, tile_pos)) = storage else { return };
+        if let Some(entity) = storage.get(&tile_pos) {
+            commands.entity(entity).insert(floor);
+            storage.set_changed();
+        }
     }
 
-    pub fn get_floor_tile<'a>(
+    fn storage_mut<'a>(
         &self,
-        wpos: Vec2,
-        chunk_query: &'a ChunkQuery,
-    ) -> Option<TileEntry<'a>> {
-        self.get_tile(wpos, chunk_query, |chunk| chunk.floor)
+        tile: IVec2,
+        chunk_query: &'a mut ChunkQueryMut,
+        tilemap: impl FnOnce(&Chunk) -> Entity,
+    ) -> Option<(Mut<'a, TileStorage>, TilePos)> {
+        let cpos = tile_to_cpos(tile);
+        let storage = chunk_query.get_mut(tilemap(self.chunks.get(&cpos)?)).ok()?;
+        let tile = tile - cpos * CHUNK_SIZE as i32;
+        Some((storage, TilePos::new(tile.x as u32, tile.y as u32)))
     }
-}
-
-const TRANS_TILE_LUT: [u32; 255] = {
-    let mut table = [0; 255];
-    let mut i = 0;
-
-    loop {
-        if i >= 255 {
-            break;
-        }
 
-        // Don't ask.
-        table[i] = match i as u8 + 1 {
-            bits if bits & 0b01111101 == 0b00000001 => 0,
-            bits if bits == 0b00000010 => 1,
-            bits if bits & 0b11110101 == 0b00000100 => 2,
-            bits if bits & 0b01110101 == 0b00000101 => 3,
-            bits if bits == 0b00001000 => 4,
-            bits if bits & 0b01111101 == 0b00001001 => 5,
-            bits if bits == 0b00001010 => 6,
-            bits if bits & 0b11010111 == 0b00010000 => 7,
-            bits if bits & 0b01010101 == 0b00010001 => 8,
-            bits if bits & 0b11010111 == 0b00010010 => 9,
-            bits if bits & 0b11010101 == 0b00010100 => 10,
-            bits if bits & 0b01010101 == 0b00010101 => 11,
-            bits if bits == 0b00100000 => 12,
-            bits if bits & 0b01111101 == 0b00100001 => 13,
-            bits if bits == 0b00100010 => 14,
-            bits if bits & 0b11110101 == 0b00100100 => 15,
-            bits if bits & 0b01110101 == 0b00100101 => 16,
-            bits if bits == 0b00101000 => 17,
-            bits if bits & 0b01111101 == 0b00101001 => 18,
-            bits if bits == 0b00101010 => 19,
-            bits if bits & 0b01011111 == 0b01000000 => 20,
-            bits if bits & 0b01011101 == 0b01000001 => 21,
-            bits if bits & 0b01011111 == 0b01000010 => 22,
-            bits if bits & 0b01010101 == 0b01000100 => 23,
-            bits if bits & 0b01010101 == 0b01000101 => 24,
-            bits if bits & 0b01011111 == 0b01001000 => 25,
-            bits if bits & 0b01011101 == 0b01001001 => 26,
-            bits if bits & 0b01011111 == 0b01001010 => 27,
-            bits if bits & 0b01010111 == 0b01010000 => 28,
-            bits if bits & 0b01010101 == 0b01010001 => 29,
-            bits if bits & 0b01010111 == 0b01010010 => 30,
-            bits if bits & 0b01010101 == 0b01010100 => 31,
-            bits if bits & 0b01010101 == 0b01010101 => 32,
-            bits if bits == 0b10000000 => 33,
-            bits if bits == 0b10000010 => 34,
-            bits if bits & 0b11110101 == 0b10000100 => 35,
-            bits if bits == 0b10001000 => 36,
-            bits if bits == 0b10001010 => 37,
-            bits if bits & 0b11010111 == 0b10010000 => 38,
-            bits if bits & 0b11010111 == 0b10010010 => 39,
-            bits if bits & 0b11010101 == 0b10010100 => 40,
-            bits if bits == 0b10100000 => 41,
-            bits if bits == 0b10100010 => 42,
-            bits if bits & 0b11110101 == 0b10100100 => 43,
-            bits if bits == 0b10101000 => 44,
-            bits if bits == 0b10101010 => 45,
-            bits if bits == 0b00000000 => 0,
-            _ => 0,
-        };
-        i += 1;
+    fn wall_storage_mut<'a>(
+        &self,
+        tile: IVec2,
+        chunk_query: &'a mut ChunkQueryMut,
+    ) -> Option<(Mut<'a, TileStorage>, TilePos)> {
+        self.storage_mut(tile, chunk_query, |chunk| chunk.walls)
     }
-    table
-};
 
-fn get_trans_tile_idx(sides: u8) -> Option<u32> {
-    sides.checked_sub(1).map(|i| TRANS_TILE_LUT[i as usize])
+    fn floor_storage_mut<'a>(
+        &self,
+        tile: IVec2,
+        chunk_query: &'a mut ChunkQueryMut,
+    ) -> Option<(Mut<'a, TileStorage>, TilePos)> {
+        self.storage_mut(tile, chunk_query, |chunk| chunk.floor)
+    }
 }
 
 const LAKE_RADIATION: f32 = 0.05;
@@ -292,7 +286,9 @@ fn spawn_chunk(
     assets: &GameAssets,
     asset_server: &AssetServer,
     atlases: &TextureAtlases,
+    deltas: &ChunkDeltas,
     chunk_pos: IVec2,
+    mut chunk_data: ChunkData,
     seed: u32,
 ) -> Chunk {
     let map_size = TilemapSize {
@@ -310,7 +306,7 @@ fn spawn_chunk(
         0.0,
     ));
     transform.scale = Vec2::splat(SCALE).extend(1.);
-    let chunk_data = gen::gen_chunk(chunk_pos, seed);
+    deltas.apply_tiles(chunk_pos, &mut chunk_data);
     let bounds = chunk_data.chunk_aabr();
 
     let field = RandomField(seed);
@@ -328,47 +324,21 @@ fn spawn_chunk(
                 };
 
                 let tile = chunk_data.get_floor_tile(x, y);
-
-                const PER_ROW: u32 = 32;
-                let is_water = |n_x, n_y| {
-                    let tile = chunk_data.get_floor_tile(x + n_x, y + n_y);
-                    (tile == gen::FloorTile::Water) as u8
-                };
-
-                let texture_index = match tile {
-                    gen::FloorTile::Ground => {
-                        let sides = is_water(1, 0)
-                            | is_water(1, 1) << 1
-                            | is_water(0, 1) << 2
-                            | is_water(-1, 1) << 3
-                            | is_water(-1, 0) << 4
-                            | is_water(-1, -1) << 5
-                            | is_water(0, -1) << 6
-                            | is_water(1, -1) << 7;
-                        if let Some(idx) = get_trans_tile_idx(sides) {
-                            PER_ROW + idx
-                        } else {
-                            field.gen_range(vek::Vec2::new(x, y), 0..=2)
-                        }
-                    }
-                    gen::FloorTile::Water => PER_ROW * 3,
-                    gen::FloorTile::Concrete => {
-                        PER_ROW * 4
-                            + match field.gen_range(vek::Vec2::new(x, y), 0..=10) {
-                                1 => 1,
-                                2 => 2,
-                                _ => 0,
-                            }
-                    }
-                };
+                let neighbour =
+                    |offset: IVec2| chunk_data.get_floor_tile(x + offset.x, y + offset.y);
+                let texture_index = floor_texture_index(tile, IVec2::new(x, y), field, neighbour);
 
                 let tile = commands
-                    .spawn(TileBundle {
-                        position: tile_pos,
-                        texture_index: TileTextureIndex(texture_index),
-                        tilemap_id: TilemapId(floor_map),
-                        ..default()
-                    })
+                    .spawn((
+                        TileBundle {
+                            position: tile_pos,
+                            texture_index: TileTextureIndex(texture_index),
+                            tilemap_id: TilemapId(floor_map),
+                            color: TileColor(floor_tint(tile)),
+                            ..default()
+                        },
+                        tile,
+                    ))
                     .id();
                 floor_storage.set(&tile_pos, tile);
             }
@@ -391,7 +361,7 @@ fn spawn_chunk(
     };
 
     let walls = {
-        let wall_image: Handle<Image> = asset_server.load("art/wall.png");
+        let wall_image: Handle<Image> = asset_server.load("art/atlas_wall.png");
         let mut wall_storage = TileStorage::empty(map_size);
         let wall_map = commands.spawn(ChunkMarker).id();
 
@@ -403,19 +373,21 @@ fn spawn_chunk(
                 };
 
                 let tile = chunk_data.get_wall_tile(x, y);
-
-                let texture_index = match tile {
-                    gen::WallTile::None => continue,
-                    gen::WallTile::Wall => 0,
-                };
+                let neighbour =
+                    |offset: IVec2| chunk_data.get_wall_tile(x + offset.x, y + offset.y);
+                let Some(texture_index) = wall_texture_index(tile, neighbour) else { continue };
 
                 let tile = commands
-                    .spawn(TileBundle {
-                        position: tile_pos,
-                        texture_index: TileTextureIndex(texture_index),
-                        tilemap_id: TilemapId(wall_map),
-                        ..default()
-                    })
+                    .spawn((
+                        TileBundle {
+                            position: tile_pos,
+                            texture_index: TileTextureIndex(texture_index),
+                            tilemap_id: TilemapId(wall_map),
+                            ..default()
+                        },
+                        tile,
+                        WallHealth::new(tile),
+                    ))
                     .id();
                 wall_storage.set(&tile_pos, tile);
             }
@@ -437,23 +409,15 @@ fn spawn_chunk(
             .id()
     };
 
-    for (pos, item) in chunk_data.items {
-        commands.spawn((
-            SpriteBundle {
-                texture: assets.items[item].clone(),
-                transform: Transform::from_translation(as_object_vec3(pos * TILE_SIZE)),
-                ..default()
-            },
-            item,
-        ));
-    }
-
-    for (pos, construct) in chunk_data.constructs {
-        construct.bundle(pos * TILE_SIZE, assets).spawn(commands);
+    for (object, pos, kind) in deltas.objects(chunk_pos, &chunk_data) {
+        let entity = kind.spawn(pos, commands, assets);
+        commands.entity(entity).insert(object.bundle());
     }
 
     for (pos, enemy) in chunk_data.enemies {
-        enemy.spawn(pos * TILE_SIZE, commands, atlases);
+        enemy
+            .spawn(pos * TILE_SIZE, commands, atlases)
+            .insert(InChunk(chunk_pos));
     }
 
     for (pos, radius) in chunk_data.lakes {
@@ -464,25 +428,68 @@ fn spawn_chunk(
                 active: true,
             },
             Transform::from_translation(as_object_vec3(pos * TILE_SIZE)),
+            InChunk(chunk_pos),
         ));
     }
 
     Chunk { floor, walls }
 }
 
-fn wpos_to_cpos(wpos: Vec2) -> IVec2 {
+pub fn wpos_to_tile(wpos: Vec2) -> IVec2 {
+    (wpos / TILE_SIZE).round().as_ivec2()
+}
+
+pub fn tile_to_cpos(tile: IVec2) -> IVec2 {
+    IVec2::new(
+        tile.x.div_euclid(CHUNK_SIZE as i32),
+        tile.y.div_euclid(CHUNK_SIZE as i32),
+    )
+}
+
+pub fn wpos_to_cpos(wpos: Vec2) -> IVec2 {
     (wpos / (CHUNK_SIZE as f32 * TILE_SIZE)).floor().as_ivec2()
 }
 
-#[derive(Deref, DerefMut)]
-struct Seed(u32);
+/// Closest to a camera first
+#[derive(Eq, PartialEq)]
+struct QueuedChunk {
+    distance: FloatOrd,
+    cpos: IVec2,
+}
 
-impl Default for Seed {
-    fn default() -> Self {
-        Self(thread_rng().gen())
+impl QueuedChunk {
+    fn new(cpos: IVec2, cameras: &[Vec2]) -> Self {
+        let center = (cpos.as_vec2() + 0.5) * CHUNK_SIZE as f32 * TILE_SIZE;
+        let distance = cameras
+            .iter()
+            .map(|camera| camera.distance_squared(center))
+            .fold(f32::INFINITY, f32::min);
+
+        Self {
+            distance: FloatOrd(distance),
+            cpos,
+        }
     }
 }
 
+impl Ord for QueuedChunk {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.cmp(&self.distance)
+    }
+}
+
+impl PartialOrd for QueuedChunk {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn in_range(cpos: IVec2, cameras: &[Vec2], radius: i32) -> bool {
+    cameras
+        .iter()
+        .any(|&camera| (cpos - wpos_to_cpos(camera)).abs().max_element() <= radius)
+}
+
 fn spawn_chunks_around_camera(
     mut commands: Commands,
     camera_query: Query<&Transform, With<Camera>>,
@@ -490,47 +497,258 @@ fn spawn_chunks_around_camera(
     assets: Res<GameAssets>,
     atlases: Res<TextureAtlases>,
     mut chunk_manager: ResMut<ChunkManager>,
-    seed: Local<Seed>,
+    deltas: Res<ChunkDeltas>,
+    seed: Res<WorldSeed>,
+    templates: Res<Assets<StructureTemplates>>,
+    loot: Res<Assets<LootTables>>,
 ) {
-    for transform in camera_query.iter() {
-        let camera_chunk_pos = wpos_to_cpos(transform.translation.xy());
-        for y in (camera_chunk_pos.y - 2)..=(camera_chunk_pos.y + 2) {
-            for x in (camera_chunk_pos.x - 2)..=(camera_chunk_pos.x + 2) {
-                let cpos = IVec2::new(x, y);
-                if !chunk_manager.chunks.contains_key(&cpos) {
-                    let chunk = spawn_chunk(
-                        &mut commands,
-                        &assets,
-                        &asset_server,
-                        &atlases,
-                        cpos,
-                        **seed,
-                    );
-                    chunk_manager.chunks.insert(cpos, chunk);
-                    // Don't generate more than one chunk per tick.
-                    return;
+    // Generation needs the templates and loot tables, so wait for them to load
+    let Some(templates) = templates.get(&assets.structures) else { return };
+    let Some(loot) = loot.get(&assets.loot) else { return };
+
+    let cameras = camera_query
+        .iter()
+        .map(|transform| transform.translation.xy())
+        .collect::<Vec<_>>();
+    let chunk_manager = &mut *chunk_manager;
+
+    // Dropping a task cancels it
+    chunk_manager
+        .generating
+        .retain(|&cpos, _| in_range(cpos, &cameras, UNLOAD_RADIUS));
+    chunk_manager
+        .generated
+        .retain(|&cpos, _| in_range(cpos, &cameras, UNLOAD_RADIUS));
+
+    let finished = chunk_manager
+        .generating
+        .iter_mut()
+        .filter_map(|(&cpos, task)| now_or_never(task).map(|chunk_data| (cpos, chunk_data)))
+        .collect::<Vec<_>>();
+
+    for (cpos, chunk_data) in finished {
+        chunk_manager.generating.remove(&cpos);
+        chunk_manager.generated.insert(cpos, chunk_data);
+    }
+
+    let mut queue = BinaryHeap::new();
+    for &camera in &cameras {
+        let camera_cpos = wpos_to_cpos(camera);
+        for y in -GEN_RADIUS..=GEN_RADIUS {
+            for x in -GEN_RADIUS..=GEN_RADIUS {
+                let cpos = camera_cpos + IVec2::new(x, y);
+                if !chunk_manager.is_tracked(cpos) {
+                    queue.push(QueuedChunk::new(cpos, &cameras));
                 }
             }
         }
     }
+
+    let pool = AsyncComputeTaskPool::get();
+    let seed = **seed;
+    while chunk_manager.generating.len() < MAX_GENERATING {
+        let Some(QueuedChunk { cpos, .. }) = queue.pop() else { break };
+
+        // Cameras' ranges can overlap
+        if chunk_manager.generating.contains_key(&cpos) {
+            continue;
+        }
+
+        let templates = templates.clone();
+        let loot = loot.clone();
+        chunk_manager.generating.insert(
+            cpos,
+            pool.spawn(async move { gen::gen_chunk(cpos, seed, &templates, &loot) }),
+        );
+    }
+
+    let mut ready = chunk_manager
+        .generated
+        .keys()
+        .map(|&cpos| QueuedChunk::new(cpos, &cameras))
+        .collect::<BinaryHeap<_>>();
+
+    for _ in 0..MAX_SPAWNED_PER_TICK {
+        let Some(QueuedChunk { cpos, .. }) = ready.pop() else { break };
+        let Some(chunk_data) = chunk_manager.generated.remove(&cpos) else { continue };
+
+        let chunk = spawn_chunk(
+            &mut commands,
+            &assets,
+            &asset_server,
+            &atlases,
+            &deltas,
+            cpos,
+            chunk_data,
+            seed,
+        );
+        chunk_manager.chunks.insert(cpos, chunk);
+    }
 }
 
 fn despawn_outofrange_chunks(
     mut commands: Commands,
     camera_query: Query<&Transform, With<Camera>>,
     chunks_query: Query<(Entity, &Transform), With<ChunkMarker>>,
+    owned: Query<(
+        Entity,
+        &InChunk,
+        Option<&UnloadPolicy>,
+        &Transform,
+        Option<&ChunkObject>,
+        Option<&Item>,
+        Option<&Construct>,
+    )>,
     mut chunk_manager: ResMut<ChunkManager>,
+    mut deltas: ResMut<ChunkDeltas>,
 ) {
+    let mut unloaded = HashSet::new();
+
+    let cameras = camera_query
+        .iter()
+        .map(|transform| transform.translation.xy())
+        .collect::<Vec<_>>();
+
     for (entity, chunk_transform) in chunks_query.iter() {
         let cpos = wpos_to_cpos(chunk_transform.translation.xy());
 
-        if camera_query.iter().all(|camera_transform| {
-            let camera_cpos = wpos_to_cpos(camera_transform.translation.xy());
-            let distance = (cpos - camera_cpos).abs().max_element();
-            distance > 3
-        }) {
+        if !in_range(cpos, &cameras, UNLOAD_RADIUS) {
             chunk_manager.chunks.remove(&cpos);
             commands.entity(entity).despawn_recursive();
+            unloaded.insert(cpos);
+        }
+    }
+
+    if unloaded.is_empty() {
+        return;
+    }
+
+    for (entity, in_chunk, policy, transform, object, item, construct) in &owned {
+        if unloaded.contains(&**in_chunk) {
+            unload_entity(
+                &mut commands,
+                &mut deltas,
+                entity,
+                policy,
+                transform.translation.xy(),
+                object,
+                Object::from_components(item, construct),
+            );
+        }
+    }
+}
+
+/// Also forgets the changes made to the world, so it generates from scratch
+pub fn unload_world(world: &mut World) {
+    let mut system_state = SystemState::<(
+        Commands,
+        Query<Entity, Or<(With<ChunkMarker>, With<InChunk>)>>,
+        ResMut<ChunkManager>,
+        ResMut<ChunkDeltas>,
+    )>::new(world);
+    let (mut commands, entities, mut chunk_manager, mut deltas) = system_state.get_mut(world);
+
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+    // Chunks still generating are cancelled when their tasks drop
+    *chunk_manager = default();
+    *deltas = default();
+
+    system_state.apply(world);
+}
+
+fn update_chunk_owners(
+    mut commands: Commands,
+    mut owned: Query<
+        (
+            Entity,
+            &mut InChunk,
+            Option<&UnloadPolicy>,
+            &Transform,
+            Option<&ChunkObject>,
+            Option<&Item>,
+            Option<&Construct>,
+        ),
+        Changed<Transform>,
+    >,
+    chunk_manager: Res<ChunkManager>,
+    mut deltas: ResMut<ChunkDeltas>,
+) {
+    for (entity, mut in_chunk, policy, transform, object, item, construct) in &mut owned {
+        let pos = transform.translation.xy();
+        let cpos = wpos_to_cpos(pos);
+        if cpos == **in_chunk {
+            continue;
+        }
+
+        **in_chunk = cpos;
+
+        // Wandered off into a chunk that isn't loaded or on its way
+        if !chunk_manager.is_tracked(cpos) {
+            unload_entity(
+                &mut commands,
+                &mut deltas,
+                entity,
+                policy,
+                pos,
+                object,
+                Object::from_components(item, construct),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_floor_on_chunk_edge() {
+        let mut world = World::new();
+        world.init_resource::<ChunkDeltas>();
+
+        let size = TilemapSize {
+            x: CHUNK_SIZE,
+            y: CHUNK_SIZE,
+        };
+        let floor = world.spawn(TileStorage::empty(size)).id();
+        let walls = world.spawn(TileStorage::empty(size)).id();
+
+        let mut chunks = ChunkManager::default();
+        chunks.chunks.insert(IVec2::ZERO, Chunk { floor, walls });
+        world.insert_resource(chunks);
+
+        // Row 0 and column 0 of the chunk
+        let tile = world.spawn(FloorTile::Ground).id();
+        let mut storage = world.get_mut::<TileStorage>(floor).unwrap();
+        storage.set(&TilePos::new(0, 0), tile);
+
+        fn flood(
+            mut commands: Commands,
+            chunks: Res<ChunkManager>,
+            mut chunk_query: ChunkQueryMut,
+            mut deltas: ResMut<ChunkDeltas>,
+        ) {
+            chunks.set_floor(
+                Vec2::ZERO,
+                FloorTile::Water,
+                &mut commands,
+                &mut chunk_query,
+                &mut deltas,
+            );
         }
+        let mut schedule = Schedule::new();
+        schedule.add_system(flood);
+        schedule.run(&mut world);
+
+        assert!(world.get::<FloorTile>(tile) == Some(&FloorTile::Water));
+
+        // Survives the chunk regenerating
+        let mut chunk_data = ChunkData::default();
+        world
+            .resource::<ChunkDeltas>()
+            .apply_tiles(IVec2::ZERO, &mut chunk_data);
+        assert!(chunk_data.get_floor_tile(0, 0) == FloorTile::Water);
     }
 }
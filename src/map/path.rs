@@ -0,0 +1,170 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy::{ecs::system::SystemParam, utils::HashMap};
+
+use crate::prelude::*;
+
+use super::{
+    autotile::tile_entity, gen::WallTile, tile_to_cpos, wpos_to_tile, ChunkManager, TILE_SIZE,
+};
+
+/// Tiles a search visits before settling for the closest one
+const MAX_VISITED: usize = 2048;
+/// Seconds before a path is re-planned anyway
+const REPLAN_INTERVAL: f32 = 2.;
+const WAYPOINT_RADIUS: f32 = TILE_SIZE / 4.;
+
+/// Bumped whenever a loaded chunk's walls change
+#[derive(Default, Resource)]
+pub struct WallVersion(u32);
+
+pub fn track_wall_changes(
+    chunk_manager: Res<ChunkManager>,
+    changed: Query<(), Changed<TileStorage>>,
+    mut version: ResMut<WallVersion>,
+) {
+    if chunk_manager
+        .chunks
+        .values()
+        .any(|chunk| changed.contains(chunk.walls))
+    {
+        version.0 = version.0.wrapping_add(1);
+    }
+}
+
+/// Unloaded tiles are treated as walls
+#[derive(SystemParam)]
+pub struct Pathfinder<'w, 's> {
+    chunk_manager: Res<'w, ChunkManager>,
+    storages: Query<'w, 's, &'static TileStorage>,
+    walls: Query<'w, 's, &'static WallTile>,
+    version: Res<'w, WallVersion>,
+}
+
+impl<'w, 's> Pathfinder<'w, 's> {
+    pub fn walkable(&self, tile: IVec2) -> bool {
+        let chunk_manager = &self.chunk_manager;
+        if !chunk_manager.chunks.contains_key(&tile_to_cpos(tile)) {
+            return false;
+        }
+
+        let wall = tile_entity(chunk_manager, &self.storages, tile, |chunk| chunk.walls);
+        let Some(entity) = wall else { return true };
+        let Ok(wall) = self.walls.get(entity) else { return false };
+        !wall.blocks(false)
+    }
+
+    /// Tiles to walk through after `start`. If `goal` can't be reached, leads to the closest tile.
+    pub fn find(&self, start: IVec2, goal: IVec2) -> Vec<IVec2> {
+        let heuristic = |tile: IVec2| {
+            let distance = (goal - tile).abs();
+            distance.x + distance.y
+        };
+
+        let mut open = BinaryHeap::from([QueuedTile {
+            estimate: heuristic(start),
+            tile: start,
+        }]);
+        let mut costs = HashMap::from([(start, 0)]);
+        let mut came_from = HashMap::<IVec2, IVec2>::default();
+        let mut closest = start;
+
+        while let Some(QueuedTile { estimate, tile }) = open.pop() {
+            // A cheaper way here was queued after this one, and already expanded
+            if estimate - heuristic(tile) > costs[&tile] {
+                continue;
+            }
+            if heuristic(tile) < heuristic(closest) {
+                closest = tile;
+            }
+            if tile == goal || costs.len() >= MAX_VISITED {
+                break;
+            }
+
+            let cost = costs[&tile] + 1;
+            for offset in [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y] {
+                let next = tile + offset;
+                if matches!(costs.get(&next), Some(&old) if old <= cost) || !self.walkable(next) {
+                    continue;
+                }
+
+                costs.insert(next, cost);
+                came_from.insert(next, tile);
+                open.push(QueuedTile {
+                    estimate: cost + heuristic(next),
+                    tile: next,
+                });
+            }
+        }
+
+        let mut path = vec![closest];
+        while let Some(&prev) = came_from.get(path.last().unwrap()) {
+            path.push(prev);
+        }
+        path.pop();
+        path.reverse();
+        path
+    }
+}
+
+/// Shortest estimate first
+#[derive(Eq, PartialEq)]
+struct QueuedTile {
+    estimate: i32,
+    tile: IVec2,
+}
+
+impl Ord for QueuedTile {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.cmp(&self.estimate)
+    }
+}
+
+impl PartialOrd for QueuedTile {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Re-planned when the goal changes tile, walls change, or it gets old
+#[derive(Component, Default)]
+pub struct Path {
+    /// Next last
+    tiles: Vec<IVec2>,
+    goal: Option<IVec2>,
+    version: u32,
+    age: f32,
+}
+
+impl Path {
+    /// `dt` is the time since the last call
+    pub fn direction(&mut self, pos: Vec2, goal: Vec2, pathfinder: &Pathfinder, dt: f32) -> Vec2 {
+        let goal_tile = wpos_to_tile(goal);
+        self.age += dt;
+
+        if self.goal != Some(goal_tile)
+            || self.version != pathfinder.version.0
+            || self.age >= REPLAN_INTERVAL
+        {
+            self.tiles = pathfinder.find(wpos_to_tile(pos), goal_tile);
+            self.tiles.reverse();
+            self.goal = Some(goal_tile);
+            self.version = pathfinder.version.0;
+            self.age = 0.;
+        }
+
+        while let Some(&tile) = self.tiles.last() {
+            if (tile.as_vec2() * TILE_SIZE).distance(pos) > WAYPOINT_RADIUS {
+                break;
+            }
+            self.tiles.pop();
+        }
+
+        // Past the last tile, head straight for the goal
+        let waypoint = match self.tiles.last() {
+            Some(tile) => tile.as_vec2() * TILE_SIZE,
+            None => goal,
+        };
+        (waypoint - pos).normalize_or_zero()
+    }
+}
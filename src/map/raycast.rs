@@ -0,0 +1,137 @@
+use crate::prelude::*;
+
+use super::{gen::WallTile, ChunkManager, ChunkQuery, TILE_SIZE};
+
+/// Where a ray ran into a wall
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub tile: IVec2,
+    /// Where the ray entered the tile
+    pub point: Vec2,
+    /// Zero if the ray started inside the wall
+    pub normal: Vec2,
+}
+
+impl ChunkManager {
+    /// The first wall from `start` to `end`. Unloaded tiles don't block.
+    pub fn raycast(
+        &self,
+        start: Vec2,
+        end: Vec2,
+        chunk_query: &ChunkQuery,
+        walls: &Query<&WallTile>,
+        friendly: bool,
+    ) -> Option<RayHit> {
+        // In tile units, with tile `(x, y)` covering `x..x + 1` and `y..y + 1`
+        let origin = start / TILE_SIZE + 0.5;
+        let delta = (end - start) / TILE_SIZE;
+        let step = delta.signum().as_ivec2();
+        // How far along the ray it takes to cross a whole tile on each axis
+        let t_delta = (1. / delta).abs();
+
+        let mut tile = origin.floor().as_ivec2();
+        let edge = tile.as_vec2() + step.max(IVec2::ZERO).as_vec2();
+        // How far along the ray the next tile boundary is on each axis
+        let mut t_max = Vec2::select(
+            delta.cmpeq(Vec2::ZERO),
+            Vec2::splat(f32::INFINITY),
+            (edge - origin) / delta,
+        );
+        let mut t = 0.;
+        let mut normal = Vec2::ZERO;
+
+        loop {
+            if self.wall_blocks(tile, chunk_query, walls, friendly) {
+                return Some(RayHit {
+                    tile,
+                    point: start + (end - start) * t,
+                    normal,
+                });
+            }
+
+            if t_max.x < t_max.y {
+                t = t_max.x;
+                t_max.x += t_delta.x;
+                tile.x += step.x;
+                normal = Vec2::new(-step.x as f32, 0.);
+            } else {
+                t = t_max.y;
+                t_max.y += t_delta.y;
+                tile.y += step.y;
+                normal = Vec2::new(0., -step.y as f32);
+            }
+
+            if t > 1. {
+                return None;
+            }
+        }
+    }
+
+    pub fn line_of_sight(
+        &self,
+        start: Vec2,
+        end: Vec2,
+        chunk_query: &ChunkQuery,
+        walls: &Query<&WallTile>,
+        friendly: bool,
+    ) -> bool {
+        self.raycast(start, end, chunk_query, walls, friendly)
+            .is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use crate::map::{Chunk, CHUNK_SIZE};
+
+    use super::*;
+
+    fn raycast(walls: &[IVec2], start: Vec2, end: Vec2) -> Option<RayHit> {
+        let mut world = World::new();
+        let size = TilemapSize {
+            x: CHUNK_SIZE,
+            y: CHUNK_SIZE,
+        };
+        let mut storage = TileStorage::empty(size);
+        for &tile in walls {
+            let wall = world.spawn(WallTile::Wall).id();
+            storage.set(&TilePos::new(tile.x as u32, tile.y as u32), wall);
+        }
+        let floor = world.spawn(TileStorage::empty(size)).id();
+        let walls = world.spawn(storage).id();
+
+        let mut chunks = ChunkManager::default();
+        chunks.chunks.insert(IVec2::ZERO, Chunk { floor, walls });
+        world.insert_resource(chunks);
+
+        let mut system_state =
+            SystemState::<(Res<ChunkManager>, ChunkQuery, Query<&WallTile>)>::new(&mut world);
+        let (chunks, chunk_query, walls) = system_state.get(&world);
+        chunks.raycast(start, end, &chunk_query, &walls, false)
+    }
+
+    #[test]
+    fn hits_wall_along_axis() {
+        let hit = raycast(&[IVec2::new(0, 4)], Vec2::ZERO, Vec2::new(0., 192.)).unwrap();
+        assert_eq!(hit.tile, IVec2::new(0, 4));
+        assert!(hit.point.distance(Vec2::new(0., 3.5 * TILE_SIZE)) < 0.001);
+        assert_eq!(hit.normal, Vec2::new(0., -1.));
+    }
+
+    #[test]
+    fn hits_wall_diagonally() {
+        let hit = raycast(&[IVec2::new(3, 1)], Vec2::ZERO, Vec2::new(192., 96.)).unwrap();
+        assert_eq!(hit.tile, IVec2::new(3, 1));
+        let point = Vec2::new(2.5, 1.25) * TILE_SIZE;
+        assert!(hit.point.distance(point) < 0.001);
+        assert_eq!(hit.normal, Vec2::new(-1., 0.));
+    }
+
+    #[test]
+    fn misses_walls_past_the_end() {
+        assert!(raycast(&[IVec2::new(5, 0)], Vec2::ZERO, Vec2::new(128., 0.)).is_none());
+    }
+}
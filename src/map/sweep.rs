@@ -0,0 +1,59 @@
+use crate::prelude::*;
+
+use super::{gen::WallTile, ChunkManager, ChunkQuery, TILE_SIZE};
+
+/// Keeps boxes that are exactly touching a tile from counting as overlapping it
+const EPSILON: f32 = 0.001;
+
+fn tile_index(coord: f32) -> i32 {
+    (coord / TILE_SIZE + 0.5).floor() as i32
+}
+
+impl ChunkManager {
+    /// Moves a box along `axis` until it's flush against the first wall in the way, so nothing
+    /// tunnels through walls. Returns the new center and the wall's tile.
+    pub fn sweep(
+        &self,
+        pos: Vec2,
+        half: Vec2,
+        delta: f32,
+        axis: usize,
+        chunk_query: &ChunkQuery,
+        walls: &Query<&WallTile>,
+        friendly: bool,
+    ) -> (Vec2, Option<IVec2>) {
+        let mut end = pos;
+        end[axis] += delta;
+        if delta == 0. {
+            return (end, None);
+        }
+
+        let sign = delta.signum();
+        let step = sign as i32;
+        // The tile the leading edge is in, not counting tiles it's only touching
+        let leading = |edge: f32| tile_index(edge - sign * EPSILON);
+        let edge = pos[axis] + half[axis] * sign;
+        let last = leading(edge + delta);
+
+        let other = 1 - axis;
+        let first_row = tile_index(pos[other] - half[other] + EPSILON);
+        let last_row = tile_index(pos[other] + half[other] - EPSILON).max(first_row);
+
+        let mut column = leading(edge);
+        while column != last {
+            column += step;
+            for row in first_row..=last_row {
+                let mut tile = IVec2::ZERO;
+                tile[axis] = column;
+                tile[other] = row;
+                if self.wall_blocks(tile, chunk_query, walls, friendly) {
+                    let boundary = (column as f32 - 0.5 * sign) * TILE_SIZE;
+                    end[axis] = boundary - half[axis] * sign;
+                    return (end, Some(tile));
+                }
+            }
+        }
+
+        (end, None)
+    }
+}
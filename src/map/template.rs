@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use bevy::reflect::TypeUuid;
+use serde::Deserialize;
+use vek::*;
+
+use crate::{asset::RonAsset, construct::Construct};
+
+use super::{biome::Biome, CHUNK_SIZE};
+
+/// Structures must fit in the area `gen_chunk` searches for them
+const MAX_SIZE: usize = CHUNK_SIZE as usize * 2;
+
+#[derive(Clone, Copy)]
+pub enum TemplateTile {
+    /// `' '`: leaves the generated tile alone
+    Empty,
+    /// `'.'`
+    Floor,
+    /// `'#'`
+    Wall,
+    /// `'+'`
+    Door,
+    /// `'L'`: floor with an item from the loot table on it, sometimes
+    Loot,
+    /// `'A'`, `'G'` or `'T'`: floor with an assembler, generator or turret on it, sometimes
+    Construct(Construct),
+    /// `'E'`: floor with an enemy on it, sometimes
+    Guard,
+}
+
+impl TemplateTile {
+    fn from_char(tile: char) -> Option<Self> {
+        Some(match tile {
+            ' ' => Self::Empty,
+            '.' => Self::Floor,
+            '#' => Self::Wall,
+            '+' => Self::Door,
+            'L' => Self::Loot,
+            'A' => Self::Construct(Construct::Assembler),
+            'G' => Self::Construct(Construct::Generator),
+            'T' => Self::Construct(Construct::Turret),
+            'E' => Self::Guard,
+            _ => return None,
+        })
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_loot_chance() -> f32 {
+    super::gen::ITEM_CHANCE
+}
+
+fn default_construct_chance() -> f32 {
+    super::gen::ASSEMBLER_CHANCE
+}
+
+#[derive(Deserialize)]
+pub struct RawStructureTemplate {
+    name: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    #[serde(default)]
+    biomes: Vec<Biome>,
+    #[serde(default)]
+    loot: Option<String>,
+    #[serde(default = "default_loot_chance")]
+    loot_chance: f32,
+    #[serde(default = "default_construct_chance")]
+    construct_chance: f32,
+    /// Top row first
+    tiles: Vec<String>,
+}
+
+pub struct StructureTemplate {
+    pub weight: u32,
+    /// Biomes it generates in. Empty means all of them.
+    pub biomes: Vec<Biome>,
+    /// `None` uses the biome's loot table
+    pub loot: Option<String>,
+    pub loot_chance: f32,
+    pub construct_chance: f32,
+    size: Extent2<i32>,
+    /// Bottom row first
+    tiles: Vec<TemplateTile>,
+}
+
+impl StructureTemplate {
+    fn from_raw(raw: RawStructureTemplate) -> Result<Self, bevy::asset::Error> {
+        let name = raw.name;
+        let width = raw.tiles.first().map_or(0, |row| row.chars().count());
+
+        if width == 0 {
+            return Err(bevy::asset::Error::msg(format!(
+                "Structure `{name}` has no tiles"
+            )));
+        }
+
+        if width > MAX_SIZE || raw.tiles.len() > MAX_SIZE {
+            return Err(bevy::asset::Error::msg(format!(
+                "Structure `{name}` is bigger than {MAX_SIZE} by {MAX_SIZE} tiles"
+            )));
+        }
+
+        if raw.weight == 0 {
+            return Err(bevy::asset::Error::msg(format!(
+                "Structure `{name}` has a weight of 0"
+            )));
+        }
+
+        let mut tiles = Vec::with_capacity(width * raw.tiles.len());
+        for (y, row) in raw.tiles.iter().rev().enumerate() {
+            if row.chars().count() != width {
+                return Err(bevy::asset::Error::msg(format!(
+                    "Row {y} from the bottom of structure `{name}` isn't {width} tiles wide"
+                )));
+            }
+
+            for tile in row.chars() {
+                tiles.push(TemplateTile::from_char(tile).ok_or_else(|| {
+                    bevy::asset::Error::msg(format!("Structure `{name}` has unknown tile `{tile}`"))
+                })?);
+            }
+        }
+
+        Ok(Self {
+            size: Extent2::new(width as i32, raw.tiles.len() as i32),
+            weight: raw.weight,
+            biomes: raw.biomes,
+            loot: raw.loot,
+            loot_chance: raw.loot_chance,
+            construct_chance: raw.construct_chance,
+            tiles,
+        })
+    }
+
+    pub fn generates_in(&self, biome: Biome) -> bool {
+        self.biomes.is_empty() || self.biomes.contains(&biome)
+    }
+
+    /// After `rotation` quarter turns
+    pub fn size(&self, rotation: u32) -> Extent2<i32> {
+        match rotation % 2 {
+            0 => self.size,
+            _ => Extent2::new(self.size.h, self.size.w),
+        }
+    }
+
+    /// Offsets are from the bottom left corner, after `rotation` quarter turns
+    pub fn tiles(&self, rotation: u32) -> impl Iterator<Item = (Vec2<i32>, TemplateTile)> + '_ {
+        let Extent2 { w, h } = self.size;
+
+        self.tiles.iter().enumerate().map(move |(i, &tile)| {
+            let (x, y) = (i as i32 % w, i as i32 / w);
+            let offset = match rotation % 4 {
+                0 => Vec2::new(x, y),
+                1 => Vec2::new(h - 1 - y, x),
+                2 => Vec2::new(w - 1 - x, h - 1 - y),
+                _ => Vec2::new(y, w - 1 - x),
+            };
+
+            (offset, tile)
+        })
+    }
+}
+
+#[derive(Clone, Default, TypeUuid)]
+#[uuid = "04646bfc-28a3-45f2-8960-0c0043374118"]
+pub struct StructureTemplates(Arc<Vec<StructureTemplate>>);
+
+impl StructureTemplates {
+    pub fn iter(&self) -> impl Iterator<Item = &StructureTemplate> {
+        self.0.iter()
+    }
+}
+
+impl RonAsset for StructureTemplates {
+    type Raw = Vec<RawStructureTemplate>;
+
+    const EXTENSION: &'static str = "structures.ron";
+
+    fn from_raw(raw: Self::Raw) -> Result<Self, bevy::asset::Error> {
+        Ok(Self(Arc::new(
+            raw.into_iter()
+                .map(StructureTemplate::from_raw)
+                .collect::<Result<_, _>>()?,
+        )))
+    }
+}
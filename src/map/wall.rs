@@ -0,0 +1,224 @@
+use bevy::ecs::system::SystemState;
+
+use crate::{
+    item::{remove_item_at, Inventory, InventorySlot, Item},
+    physics::{Collider, CollisionLayers},
+    player::{Heading, Player},
+    prelude::*,
+};
+
+use super::{gen::WallTile, wpos_to_tile, ChunkDeltas, ChunkManager, ChunkQueryMut, TILE_SIZE};
+
+/// Damage stages with their own cracks in `art/atlas_wall.png`, including undamaged
+const CRACK_STAGES: u32 = 4;
+
+impl TryFrom<Item> for WallTile {
+    type Error = ();
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        match item {
+            Item::Wall => Ok(WallTile::Wall),
+            Item::Door => Ok(WallTile::Door),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Walls can't be built on top of these, since they could walk out through them
+const BUILD_BLOCKERS: u32 =
+    CollisionLayers::PLAYER | CollisionLayers::ENEMY | CollisionLayers::CONSTRUCT;
+
+/// The item is only used up if the wall gets built
+pub fn build_wall(slot: usize, wall: WallTile) -> impl Fn(&mut World) {
+    move |world: &mut World| {
+        let mut system_state = SystemState::<(
+            Commands,
+            Query<(&Transform, &Heading), With<Player>>,
+            Query<(&Transform, &Collider, &CollisionLayers)>,
+            Res<ChunkManager>,
+            ChunkQueryMut,
+            ResMut<ChunkDeltas>,
+            Query<&mut InventorySlot>,
+            Query<&Inventory>,
+        )>::new(world);
+        let (
+            mut commands,
+            players,
+            colliders,
+            chunks,
+            mut chunk_query,
+            mut deltas,
+            mut slots,
+            inventory,
+        ) = system_state.get_mut(world);
+        let Ok((transform, heading)) = players.get_single() else { return };
+
+        let tile = wpos_to_tile(transform.translation.truncate()) + **heading;
+        let Some((storage, tile_pos)) = chunks.wall_storage_mut(tile, &mut chunk_query) else {
+            return;
+        };
+        if storage.get(&tile_pos).is_some() {
+            return;
+        }
+
+        let wpos = tile.as_vec2() * TILE_SIZE;
+        let tile_collider = Collider::Aabb(Vec2::splat(TILE_SIZE / 2.));
+        for (transform, &collider, layers) in &colliders {
+            let pos = transform.translation.truncate();
+            if layers.layers & BUILD_BLOCKERS != 0
+                && tile_collider.penetration(wpos, collider, pos).is_some()
+            {
+                return;
+            }
+        }
+
+        chunks.set_wall(wpos, wall, &mut commands, &mut chunk_query, &mut deltas);
+        remove_item_at(slot, &mut slots, inventory.single());
+        system_state.apply(world);
+    }
+}
+
+#[derive(Clone, Component, Copy, Deref, DerefMut)]
+pub struct WallHealth(pub f32);
+
+impl WallHealth {
+    pub fn new(wall: WallTile) -> Self {
+        Self(wall.max_health())
+    }
+
+    pub fn crack_stage(self, wall: WallTile) -> u32 {
+        let damage = 1. - self.0 / wall.max_health();
+        ((damage * CRACK_STAGES as f32) as u32).min(CRACK_STAGES - 1)
+    }
+}
+
+pub struct DamageWall {
+    pub tile: IVec2,
+    pub damage: f32,
+}
+
+/// Damages every wall within `radius`, and knocks back everything there
+pub struct Explosion {
+    pub pos: Vec2,
+    pub radius: f32,
+    pub damage: f32,
+    /// Impulse at the center. It falls off to nothing at `radius`.
+    pub knockback: f32,
+}
+
+pub fn explode(mut explosions: EventReader<Explosion>, mut damages: EventWriter<DamageWall>) {
+    for explosion in explosions.iter() {
+        let min = wpos_to_tile(explosion.pos - explosion.radius);
+        let max = wpos_to_tile(explosion.pos + explosion.radius);
+
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let tile = IVec2::new(x, y);
+                if (tile.as_vec2() * TILE_SIZE).distance(explosion.pos) <= explosion.radius {
+                    damages.send(DamageWall {
+                        tile,
+                        damage: explosion.damage,
+                    });
+                }
+            }
+        }
+    }
+}
+
+pub fn damage_walls(
+    mut commands: Commands,
+    mut damages: EventReader<DamageWall>,
+    chunks: Res<ChunkManager>,
+    mut chunk_query: ChunkQueryMut,
+    mut walls: Query<(&WallTile, &mut WallHealth)>,
+    mut deltas: ResMut<ChunkDeltas>,
+) {
+    for damage in damages.iter() {
+        let storage = chunks.wall_storage_mut(damage.tile, &mut chunk_query);
+        let Some((mut storage, tile_pos)) = storage else { continue };
+        let Some(entity) = storage.get(&tile_pos) else { continue };
+        let Ok((&wall, mut health)) = walls.get_mut(entity) else { continue };
+
+        let stage = health.crack_stage(wall);
+        **health -= damage.damage;
+
+        if **health <= 0. {
+            let wpos = damage.tile.as_vec2() * TILE_SIZE;
+            chunks.clear_wall(wpos, &mut commands, &mut chunk_query, &mut deltas);
+        } else if health.crack_stage(wall) != stage {
+            // Refreshes the texture
+            storage.set_changed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::{Chunk, CHUNK_SIZE};
+
+    use super::*;
+
+    #[test]
+    fn damages_wall_on_chunk_edge() {
+        let mut world = World::new();
+        world.init_resource::<ChunkDeltas>();
+        world.init_resource::<Events<DamageWall>>();
+
+        let cpos = IVec2::new(1, -1);
+        let size = TilemapSize {
+            x: CHUNK_SIZE,
+            y: CHUNK_SIZE,
+        };
+        let mut spawn_tilemap = || {
+            world
+                .spawn((
+                    TileStorage::empty(size),
+                    size,
+                    TilemapGridSize {
+                        x: TILE_SIZE,
+                        y: TILE_SIZE,
+                    },
+                    TilemapType::Square,
+                    Transform::from_translation(
+                        (cpos.as_vec2() * CHUNK_SIZE as f32 * TILE_SIZE).extend(0.),
+                    ),
+                ))
+                .id()
+        };
+        let floor = spawn_tilemap();
+        let walls = spawn_tilemap();
+
+        let mut chunks = ChunkManager::default();
+        chunks.chunks.insert(cpos, Chunk { floor, walls });
+        world.insert_resource(chunks);
+
+        // Row 0 and column 0 of the chunk
+        let tile = cpos * CHUNK_SIZE as i32;
+        let wall = world
+            .spawn((WallTile::Wall, WallHealth::new(WallTile::Wall)))
+            .id();
+        let mut storage = world.get_mut::<TileStorage>(walls).unwrap();
+        storage.set(&TilePos::new(0, 0), wall);
+
+        world.send_event(DamageWall {
+            tile,
+            damage: WallTile::Wall.max_health() / 2.,
+        });
+        let mut schedule = Schedule::new();
+        schedule.add_system(damage_walls);
+        schedule.run(&mut world);
+        assert_eq!(
+            **world.get::<WallHealth>(wall).unwrap(),
+            WallTile::Wall.max_health() / 2.
+        );
+
+        world.send_event(DamageWall {
+            tile,
+            damage: WallTile::Wall.max_health(),
+        });
+        schedule.run(&mut world);
+        assert!(world.get_entity(wall).is_none());
+        let storage = world.get::<TileStorage>(walls).unwrap();
+        assert_eq!(storage.get(&TilePos::new(0, 0)), None);
+    }
+}
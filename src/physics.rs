@@ -1,52 +1,313 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ecs_tilemap::prelude::TileStorage;
 
-use crate::map::{get_object_z, ChunkManager, ChunkQuery};
+use crate::{
+    map::{get_object_z, wpos_to_tile, ChunkManager, ChunkQuery, FloorTile, WallTile},
+    prelude::FnPluginExt,
+    spatial::{spatial_plugin, SpatialIndex},
+    stats::{Radiation, Stat, Stats},
+};
 
 #[derive(Component, Default)]
 pub struct Vel(pub Vec2);
 
+/// Can pass through doors
 #[derive(Component)]
-pub struct DespawnOnCollide;
+pub struct Friendly;
+
+/// Entities without one collide with walls as a point
+#[derive(Clone, Component, Copy)]
+pub enum Collider {
+    /// Collides with walls as its bounding box, so it slides along them
+    Circle(f32),
+    /// Half extents
+    Aabb(Vec2),
+}
+
+impl Collider {
+    pub fn half_extents(self) -> Vec2 {
+        match self {
+            Collider::Circle(radius) => Vec2::splat(radius),
+            Collider::Aabb(half) => half,
+        }
+    }
+
+    /// How far `self` has to move to stop overlapping `other`
+    pub fn penetration(self, pos: Vec2, other: Collider, other_pos: Vec2) -> Option<Vec2> {
+        let offset = pos - other_pos;
+
+        match (self, other) {
+            (Collider::Circle(radius), Collider::Circle(other_radius)) => {
+                let distance = offset.length();
+                let overlap = radius + other_radius - distance;
+                (overlap > 0.).then(|| offset.try_normalize().unwrap_or(Vec2::X) * overlap)
+            }
+            (Collider::Aabb(half), Collider::Aabb(other_half)) => {
+                let overlap = half + other_half - offset.abs();
+                if overlap.x <= 0. || overlap.y <= 0. {
+                    None
+                } else if overlap.x < overlap.y {
+                    Some(Vec2::new(overlap.x * offset.x.signum(), 0.))
+                } else {
+                    Some(Vec2::new(0., overlap.y * offset.y.signum()))
+                }
+            }
+            (Collider::Circle(radius), Collider::Aabb(other_half)) => {
+                let closest = other_pos + offset.clamp(-other_half, other_half);
+                let outside = pos - closest;
+                if outside == Vec2::ZERO {
+                    // The center is inside the box
+                    return Collider::Aabb(Vec2::splat(radius)).penetration(pos, other, other_pos);
+                }
+
+                let distance = outside.length();
+                (distance < radius).then(|| outside / distance * (radius - distance))
+            }
+            (Collider::Aabb(_), Collider::Circle(_)) => other
+                .penetration(other_pos, self, pos)
+                .map(|penetration| -penetration),
+        }
+    }
+}
+
+/// Two entities collide if each collides with a layer the other is on
+#[derive(Clone, Component, Copy)]
+pub struct CollisionLayers {
+    pub layers: u32,
+    pub mask: u32,
+}
+
+impl CollisionLayers {
+    pub const PLAYER: u32 = 1 << 0;
+    pub const ENEMY: u32 = 1 << 1;
+    pub const CONSTRUCT: u32 = 1 << 2;
+    pub const FRIENDLY_BULLET: u32 = 1 << 3;
+    pub const ENEMY_BULLET: u32 = 1 << 4;
+
+    pub fn new(layers: u32, mask: u32) -> Self {
+        Self { layers, mask }
+    }
+
+    pub fn collides(self, other: Self) -> bool {
+        self.mask & other.layers != 0 && other.mask & self.layers != 0
+    }
+}
+
+/// Sends `Contact`s, but doesn't push or get pushed
+#[derive(Component)]
+pub struct Sensor;
+
+pub struct WallContact {
+    pub entity: Entity,
+    pub tile: IVec2,
+}
+
+/// Sent for both entities, every tick they overlap
+pub struct Contact {
+    pub entity: Entity,
+    pub other: Entity,
+}
+
+/// Affected by the floor. Holds the velocity it's actually moving at, which catches up to `Vel`.
+#[derive(Component, Default)]
+pub struct Walker(Vec2);
+
+/// Exponential decay rate per second
+const IMPULSE_DAMPING: f32 = 8.;
+
+/// Velocity from knockback, on top of `Vel`. Entities without one can't be knocked around.
+#[derive(Component, Default)]
+pub struct Impulses(Vec2);
+
+impl Impulses {
+    pub fn apply(&mut self, impulse: Vec2) {
+        self.0 += impulse;
+    }
+}
 
 pub struct TilePhysics {
+    /// Low friction is slippery
     pub friction: f32,
+    pub speed: f32,
+    /// Per second, before resistance
+    pub radiation: f32,
 }
 
-fn entity_terrain(
-    mut commands: Commands,
-    mut collider_query: Query<(Entity, &mut Transform, &Vel, Option<&DespawnOnCollide>)>,
+pub fn entity_terrain(
+    mut collider_query: Query<(
+        Entity,
+        &mut Transform,
+        &Vel,
+        Option<&Friendly>,
+        Option<&mut Walker>,
+        Option<&mut Impulses>,
+        Option<(&mut Radiation, &Stats)>,
+        Option<&Collider>,
+    )>,
     chunk_query: ChunkQuery,
+    walls: Query<&WallTile>,
+    floors: Query<&FloorTile>,
     chunks: Res<ChunkManager>,
-    time: Res<Time>,
+    time: Res<FixedTime>,
+    mut wall_contacts: EventWriter<WallContact>,
 ) {
-    for (entity, mut transform, vel, despawn_on_collide) in collider_query.iter_mut() {
-        let x = transform.translation.x + vel.0.x * time.delta_seconds();
-        let mut despawn = false;
-        if chunks
-            .get_wall_tile(Vec2::new(x, transform.translation.y), &chunk_query)
-            .is_none()
-        {
-            transform.translation.x = x;
-        } else {
-            despawn = true;
+    let floor = |pos| {
+        let tile = chunks.floor_entity(wpos_to_tile(pos), &chunk_query);
+        let Some(tile) = tile else { return default() };
+        floors.get(tile).copied().unwrap_or_default()
+    };
+    let dt = time.period.as_secs_f32();
+
+    for (entity, mut transform, vel, friendly, mut walker, mut impulses, radiation, collider) in
+        collider_query.iter_mut()
+    {
+        let friendly = friendly.is_some();
+        let half = collider.map_or(Vec2::ZERO, |collider| collider.half_extents());
+        let mut vel = vel.0;
+
+        if let Some(walker) = &mut walker {
+            let physics = floor(transform.translation.truncate()).physics();
+            let catch_up = (physics.friction * dt).min(1.);
+            walker.0 = walker.0.lerp(vel * physics.speed, catch_up);
+            vel = walker.0;
+
+            if let Some((mut radiation, stats)) = radiation {
+                **radiation += physics.radiation / stats.get(Stat::RadiationResistence) * dt;
+                **radiation = radiation.clamp(0., 1.);
+            }
+        }
+
+        if let Some(impulses) = &mut impulses {
+            vel += impulses.0;
+            impulses.0 *= (-IMPULSE_DAMPING * dt).exp();
+        }
+
+        // One axis at a time, so blocked movement slides along walls
+        let mut pos = transform.translation.truncate();
+        let mut hit = None;
+        for axis in 0..2 {
+            let delta = vel[axis] * dt;
+            let (new_pos, axis_hit) =
+                chunks.sweep(pos, half, delta, axis, &chunk_query, &walls, friendly);
+            pos = new_pos;
+
+            if axis_hit.is_some() {
+                hit = axis_hit;
+                if let Some(walker) = &mut walker {
+                    walker.0[axis] = 0.;
+                }
+                if let Some(impulses) = &mut impulses {
+                    impulses.0[axis] = 0.;
+                }
+            }
+        }
+        transform.translation = pos.extend(get_object_z(pos.y));
+
+        if let Some(tile) = hit {
+            wall_contacts.send(WallContact { entity, tile });
         }
-        let y = transform.translation.y + vel.0.y * time.delta_seconds();
-        if chunks
-            .get_wall_tile(Vec2::new(transform.translation.x, y), &chunk_query)
-            .is_none()
-        {
-            transform.translation.y = y;
-        } else {
-            despawn = true;
+    }
+}
+
+/// Entities without `Vel` don't get pushed
+pub fn entity_entity(
+    mut colliders: Query<
+        (
+            Entity,
+            &mut Transform,
+            &Collider,
+            &CollisionLayers,
+            Option<&Vel>,
+            Option<&Sensor>,
+            Option<&Friendly>,
+        ),
+        Without<TileStorage>,
+    >,
+    chunk_query: ChunkQuery,
+    walls: Query<&WallTile>,
+    chunks: Res<ChunkManager>,
+    mut index: ResMut<SpatialIndex<Collider>>,
+    mut contacts: EventWriter<Contact>,
+) {
+    let bodies = colliders
+        .iter()
+        .map(|(entity, transform, &collider, &layers, vel, sensor, _)| {
+            let pos = transform.translation.truncate();
+            let (movable, sensor) = (vel.is_some(), sensor.is_some());
+            (entity, (pos, collider, layers, movable, sensor))
+        })
+        .collect::<HashMap<_, _>>();
+    // The index is updated once a frame, so new colliders and this tick's movement aren't in it yet
+    for (&entity, &(pos, ..)) in &bodies {
+        index.insert(entity, pos);
+    }
+    let reach = bodies
+        .values()
+        .map(|&(_, collider, ..)| collider.half_extents().length())
+        .fold(0., f32::max);
+    let mut pushes = HashMap::<Entity, Vec2>::default();
+
+    for (&entity, &(pos, collider, layers, movable, sensor)) in &bodies {
+        let radius = collider.half_extents().length() + reach;
+        for (other, _) in index.within(pos, radius) {
+            // Each pair once
+            if other <= entity {
+                continue;
+            }
+            let Some(&(other_pos, other_collider, other_layers, other_movable, other_sensor)) =
+                bodies.get(&other)
+            else {
+                continue;
+            };
+            if !layers.collides(other_layers) {
+                continue;
+            }
+            let Some(penetration) = collider.penetration(pos, other_collider, other_pos) else {
+                continue;
+            };
+
+            contacts.send(Contact { entity, other });
+            contacts.send(Contact {
+                entity: other,
+                other: entity,
+            });
+
+            if sensor || other_sensor {
+                continue;
+            }
+
+            let share = match (movable, other_movable) {
+                (true, true) => 0.5,
+                (true, false) => 1.,
+                (false, true) => 0.,
+                (false, false) => continue,
+            };
+            *pushes.entry(entity).or_default() += penetration * share;
+            *pushes.entry(other).or_default() -= penetration * (1. - share);
         }
-        transform.translation.z = get_object_z(transform.translation.y);
+    }
+
+    for (entity, push) in pushes {
+        let Ok((.., collider, _, _, _, friendly)) = colliders.get(entity) else { continue };
+        let half = collider.half_extents();
+        let friendly = friendly.is_some();
+        let Ok((_, mut transform, ..)) = colliders.get_mut(entity) else { continue };
 
-        if despawn && despawn_on_collide.is_some() {
-            commands.entity(entity).despawn();
+        // Swept, so nothing gets pushed into walls
+        let mut pos = transform.translation.truncate();
+        for axis in 0..2 {
+            (pos, _) = chunks.sweep(pos, half, push[axis], axis, &chunk_query, &walls, friendly);
         }
+        transform.translation = pos.extend(get_object_z(pos.y));
     }
 }
 
 pub fn physics_plugin(app: &mut App) {
-    app.add_system(entity_terrain);
+    app.fn_plugin(spatial_plugin::<Collider>)
+        .add_event::<Contact>()
+        .add_event::<WallContact>()
+        .add_systems(
+            (entity_terrain, entity_entity.after(entity_terrain))
+                .in_schedule(CoreSchedule::FixedUpdate),
+        );
 }
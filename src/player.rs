@@ -4,10 +4,10 @@ use crate::{
     asset::GameAssets,
     camera::PlayerCamera,
     construct::PowerSource,
-    entities::EnemyBullet,
-    map::as_object_vec3,
-    physics::Vel,
+    map::{as_object_vec3, wpos_to_tile, DamageWall},
+    physics::{Collider, CollisionLayers, Friendly, Impulses, Vel, Walker},
     prelude::*,
+    projectile::Faction,
     stats::{Radiation, Stat, StatBundle, Stats},
 };
 use bevy_kira_audio::prelude::AudioReceiver;
@@ -22,19 +22,29 @@ pub fn player_plugin(app: &mut App) {
         .add_system(update_cursor_pos)
         .add_system(update_player_power)
         .add_system(audio_follow_player)
-        .add_system(player_hit_bullets);
+        .add_system(mine_walls.in_schedule(CoreSchedule::FixedUpdate));
 }
 
 #[derive(Actionlike, Clone)]
 pub enum Action {
     Move,
     Collect,
+    Mine,
 }
 
 #[derive(Component)]
 pub struct Player;
 
+/// Snapped to an axis. Walls are built in front of the player.
+#[derive(Component, Deref, DerefMut)]
+pub struct Heading(IVec2);
+
 fn init(mut commands: Commands, assets: Res<GameAssets>) {
+    spawn_player(&mut commands, &assets);
+    commands.spawn((AudioReceiver, SpatialBundle::default()));
+}
+
+pub fn spawn_player(commands: &mut Commands, assets: &GameAssets) {
     commands.spawn((
         SpriteBundle {
             transform: Transform {
@@ -59,6 +69,8 @@ fn init(mut commands: Commands, assets: Res<GameAssets>) {
                 .insert(DualAxis::left_stick(), Action::Move)
                 .insert(KeyCode::Space, Action::Collect)
                 .insert(GamepadButtonType::South, Action::Collect)
+                .insert(MouseButton::Left, Action::Mine)
+                .insert(GamepadButtonType::West, Action::Mine)
                 .build(),
             ..default()
         },
@@ -73,14 +85,23 @@ fn init(mut commands: Commands, assets: Res<GameAssets>) {
         },
         PowerSource::default(),
         Player,
+        Friendly,
+        Faction::Player,
+        Heading(IVec2::NEG_Y),
         Vel::default(),
+        Walker::default(),
+        Impulses::default(),
+        Collider::Circle(10.),
+        CollisionLayers::new(
+            CollisionLayers::PLAYER,
+            CollisionLayers::ENEMY | CollisionLayers::CONSTRUCT | CollisionLayers::ENEMY_BULLET,
+        ),
         PointLight2d {
             color: Color::ORANGE_RED,
             strength: 5.0,
             falloff: 0.45,
         },
     ));
-    commands.spawn((AudioReceiver, SpatialBundle::default()));
 }
 
 struct CurrDirection {
@@ -102,19 +123,17 @@ fn player_move(
         (
             &mut Vel,
             &mut Handle<Image>,
-            &Transform,
             &Stats,
             &ActionState<Action>,
+            &mut Heading,
         ),
         With<Player>,
     >,
-    mut cameras: Query<&mut Transform, (With<PlayerCamera>, Without<Player>)>,
-    time: Res<Time>,
     assets: Res<GameAssets>,
     mut curr_direction: Local<CurrDirection>,
 ) {
-    let Ok((mut vel, mut image, transform, stats, state)) = players.get_single_mut() else {
-        return
+    let Ok((mut vel, mut image, stats, state, mut heading)) = players.get_single_mut() else {
+        return;
     };
 
     if state.pressed(Action::Move) {
@@ -129,6 +148,14 @@ fn player_move(
         vel.0 = Vec2::ZERO;
     }
 
+    if vel.0 != Vec2::ZERO {
+        **heading = if vel.0.x.abs() >= vel.0.y.abs() {
+            IVec2::new(vel.0.x.signum() as i32, 0)
+        } else {
+            IVec2::new(0, vel.0.y.signum() as i32)
+        };
+    }
+
     let new_direction = CurrDirection {
         north: match vel.0.y.partial_cmp(&0.0) {
             Some(Ordering::Greater) => true,
@@ -163,20 +190,6 @@ fn player_move(
     .clone();
 
     *curr_direction = new_direction;
-
-    let camera_translation = &mut cameras.single_mut().translation;
-    let target = transform
-        .translation
-        .truncate()
-        .extend(camera_translation.z);
-    let dir = (target - *camera_translation).truncate();
-    let l = dir.length();
-    const CAM_SPEED: f32 = 8.0;
-    let m = if l == 0.0 { Vec2::ZERO } else { dir / l }
-        * (l * time.delta_seconds() * CAM_SPEED)
-            .max(time.delta_seconds() * CAM_SPEED)
-            .min(l);
-    *camera_translation += m.extend(0.0);
 }
 
 #[derive(Default, Deref, DerefMut, Resource)]
@@ -218,20 +231,27 @@ fn audio_follow_player(
     }
 }
 
-fn player_hit_bullets(
-    mut commands: Commands,
-    mut players: Query<(&mut Radiation, &Transform), With<Player>>,
-    bullets: Query<(Entity, &Transform), With<EnemyBullet>>,
+/// How far from the player walls can be mined
+const MINE_REACH: f32 = 96.;
+/// Wall hit points mined per second
+const MINE_DAMAGE: f32 = 5.;
+
+fn mine_walls(
+    players: Query<(&Transform, &ActionState<Action>), With<Player>>,
+    cursor_pos: Res<CursorPos>,
+    mut damages: EventWriter<DamageWall>,
+    time: Res<FixedTime>,
 ) {
-    for (mut radiation, transform) in &mut players {
-        for (bullet, bullet_transform) in &bullets {
-            if (transform.translation.truncate() - bullet_transform.translation.truncate())
-                .length_squared()
-                < 30. * 30.
-            {
-                **radiation += 0.1;
-                commands.entity(bullet).despawn();
-            }
-        }
+    let Ok((transform, state)) = players.get_single() else { return };
+
+    if !state.pressed(Action::Mine)
+        || transform.translation.truncate().distance(**cursor_pos) > MINE_REACH
+    {
+        return;
     }
+
+    damages.send(DamageWall {
+        tile: wpos_to_tile(**cursor_pos),
+        damage: MINE_DAMAGE * time.period.as_secs_f32(),
+    });
 }
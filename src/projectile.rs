@@ -0,0 +1,252 @@
+use bevy::ecs::system::EntityCommands;
+
+use crate::{
+    construct::Construct,
+    map::{ChunkDeltas, ChunkObject, DamageWall, Explosion},
+    physics::{
+        entity_entity, Collider, CollisionLayers, Contact, Friendly, Impulses, Sensor, Vel,
+        WallContact,
+    },
+    prelude::*,
+    spatial::SpatialIndex,
+    stats::{add_buff, take_damage, Buff, Damage, DamageKind, Health, Radiation},
+};
+
+pub fn projectile_plugin(app: &mut App) {
+    app.add_systems(
+        (
+            projectile_hits.after(entity_entity).before(take_damage),
+            projectile_walls.after(projectile_hits),
+            explosion_knockback.after(projectile_walls),
+            destroy_constructs.after(take_damage),
+        )
+            .in_schedule(CoreSchedule::FixedUpdate),
+    );
+}
+
+/// Which side an entity is on. Projectiles only hit entities on other sides.
+#[derive(Clone, Component, Copy, Eq, PartialEq)]
+pub enum Faction {
+    Player,
+    Enemy,
+}
+
+impl Faction {
+    fn projectile_layers(self) -> CollisionLayers {
+        match self {
+            Faction::Player => {
+                CollisionLayers::new(CollisionLayers::FRIENDLY_BULLET, CollisionLayers::ENEMY)
+            }
+            Faction::Enemy => CollisionLayers::new(
+                CollisionLayers::ENEMY_BULLET,
+                CollisionLayers::PLAYER | CollisionLayers::CONSTRUCT,
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum HitEffect {
+    Damage(f32, DamageKind),
+    Radiation(f32),
+    Buff(Buff),
+    /// Pushes the entity hit along the projectile's path, this fast
+    Knockback(f32),
+    /// Only on enemy projectiles, so turrets spare the player's walls
+    DamageWall(f32),
+    /// Damages every wall within `radius` and knocks back everything there, whatever it hits
+    Explode {
+        radius: f32,
+        damage: f32,
+        knockback: f32,
+    },
+}
+
+#[derive(Clone, Component)]
+pub struct Projectile {
+    pub faction: Faction,
+    pub radius: f32,
+    /// Entities it can pass through. It stops at the first wall either way.
+    pub pierce: u32,
+    pub effects: Vec<HitEffect>,
+    pub source: Option<Entity>,
+    hit: Vec<Entity>,
+}
+
+impl Projectile {
+    pub fn new(faction: Faction, radius: f32, pierce: u32, effects: Vec<HitEffect>) -> Self {
+        Self {
+            faction,
+            radius,
+            pierce,
+            effects,
+            source: None,
+            hit: Vec::new(),
+        }
+    }
+
+    pub fn with_source(mut self, source: Entity) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// `bundle` is its sprite, `Vel` and so on
+    pub fn spawn<'w, 's, 'a>(
+        self,
+        bundle: impl Bundle,
+        commands: &'a mut Commands<'w, 's>,
+    ) -> EntityCommands<'w, 's, 'a> {
+        let faction = self.faction;
+        let mut projectile = commands.spawn((
+            bundle,
+            Collider::Circle(self.radius),
+            faction.projectile_layers(),
+            Sensor,
+            self,
+        ));
+
+        // Friendly shots pass through doors
+        if faction == Faction::Player {
+            projectile.insert(Friendly);
+        }
+
+        projectile
+    }
+
+    fn explode(&self, pos: Vec2, explosions: &mut EventWriter<Explosion>) {
+        for effect in &self.effects {
+            if let &HitEffect::Explode {
+                radius,
+                damage,
+                knockback,
+            } = effect
+            {
+                explosions.send(Explosion {
+                    pos,
+                    radius,
+                    damage,
+                    knockback,
+                });
+            }
+        }
+    }
+}
+
+fn projectile_hits(
+    mut commands: Commands,
+    mut projectiles: Query<(&mut Projectile, &Transform, &Vel)>,
+    factions: Query<&Faction>,
+    mut radiations: Query<&mut Radiation>,
+    mut impulses: Query<&mut Impulses>,
+    mut contacts: EventReader<Contact>,
+    mut damages: EventWriter<Damage>,
+    mut explosions: EventWriter<Explosion>,
+) {
+    for contact in contacts.iter() {
+        let Ok((mut projectile, transform, vel)) = projectiles.get_mut(contact.entity) else {
+            continue;
+        };
+        let Ok(&faction) = factions.get(contact.other) else { continue };
+        if faction == projectile.faction || projectile.hit.contains(&contact.other) {
+            continue;
+        }
+        // Already spent on something else this frame
+        if projectile.hit.len() as u32 > projectile.pierce {
+            continue;
+        }
+
+        for &effect in &projectile.effects {
+            match effect {
+                HitEffect::Damage(amount, kind) => damages.send(Damage {
+                    target: contact.other,
+                    amount,
+                    kind,
+                    source: projectile.source,
+                    pos: Some(transform.translation.truncate()),
+                }),
+                HitEffect::Radiation(radiation) => {
+                    if let Ok(mut target) = radiations.get_mut(contact.other) {
+                        **target += radiation;
+                    }
+                }
+                HitEffect::Buff(buff) => commands.add(add_buff(contact.other, buff)),
+                HitEffect::Knockback(speed) => {
+                    if let Ok(mut target) = impulses.get_mut(contact.other) {
+                        target.apply(vel.0.normalize_or_zero() * speed);
+                    }
+                }
+                HitEffect::DamageWall(_) | HitEffect::Explode { .. } => (),
+            }
+        }
+        projectile.explode(transform.translation.truncate(), &mut explosions);
+
+        projectile.hit.push(contact.other);
+        if projectile.hit.len() as u32 > projectile.pierce {
+            commands.entity(contact.entity).despawn();
+        }
+    }
+}
+
+fn projectile_walls(
+    mut commands: Commands,
+    projectiles: Query<(&Projectile, &Transform)>,
+    mut wall_contacts: EventReader<WallContact>,
+    mut wall_damages: EventWriter<DamageWall>,
+    mut explosions: EventWriter<Explosion>,
+) {
+    for contact in wall_contacts.iter() {
+        let Ok((projectile, transform)) = projectiles.get(contact.entity) else { continue };
+        // Already despawned by `projectile_hits`
+        if projectile.hit.len() as u32 > projectile.pierce {
+            continue;
+        }
+
+        for &effect in &projectile.effects {
+            match effect {
+                HitEffect::DamageWall(damage) if projectile.faction == Faction::Enemy => {
+                    wall_damages.send(DamageWall {
+                        tile: contact.tile,
+                        damage,
+                    });
+                }
+                _ => (),
+            }
+        }
+        projectile.explode(transform.translation.truncate(), &mut explosions);
+
+        commands.entity(contact.entity).despawn();
+    }
+}
+
+fn explosion_knockback(
+    mut explosions: EventReader<Explosion>,
+    index: Res<SpatialIndex<Collider>>,
+    mut impulses: Query<&mut Impulses>,
+) {
+    for explosion in explosions.iter() {
+        for (entity, pos) in index.within(explosion.pos, explosion.radius) {
+            let Ok(mut target) = impulses.get_mut(entity) else { continue };
+            let offset = pos - explosion.pos;
+            let falloff = 1. - offset.length() / explosion.radius;
+            target.apply(offset.normalize_or_zero() * explosion.knockback * falloff);
+        }
+    }
+}
+
+/// Constructs that run out of health are gone for good
+fn destroy_constructs(
+    mut commands: Commands,
+    constructs: Query<(Entity, &Health, Option<&ChunkObject>), With<Construct>>,
+    mut deltas: ResMut<ChunkDeltas>,
+) {
+    for (entity, health, object) in &constructs {
+        if **health > 0. {
+            continue;
+        }
+
+        commands.entity(entity).despawn_recursive();
+        if let Some(&object) = object {
+            deltas.remove(object);
+        }
+    }
+}
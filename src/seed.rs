@@ -0,0 +1,105 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::prelude::*;
+
+/// Settings file, relative to the working directory
+const CONFIG_PATH: &str = "config.ron";
+
+pub fn seed_plugin(app: &mut App) {
+    app.insert_resource(WorldSeed::from_args_or_config())
+        .add_startup_system(init_seed_text)
+        .add_system(update_seed_text);
+}
+
+/// The seed the world is generated from. Set with `--seed <seed>` or `seed` in `config.ron`,
+/// otherwise random.
+#[derive(Clone, Copy, Deref, DerefMut, Resource)]
+pub struct WorldSeed(pub u32);
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Config {
+    seed: Option<u32>,
+}
+
+impl WorldSeed {
+    pub fn from_args_or_config() -> Self {
+        Self::from_args()
+            .or_else(Self::from_config)
+            .unwrap_or_else(|| Self(thread_rng().gen()))
+    }
+
+    fn from_args() -> Option<Self> {
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let seed = if arg == "--seed" {
+                args.next()
+            } else if let Some(seed) = arg.strip_prefix("--seed=") {
+                Some(seed.to_owned())
+            } else {
+                continue;
+            };
+
+            // Not a warning, since `dump-world` has no logger to show it
+            let Some(Ok(seed)) = seed.map(|seed| seed.parse()) else {
+                panic!("`--seed` expects a number from 0 to {}", u32::MAX);
+            };
+
+            return Some(Self(seed));
+        }
+
+        None
+    }
+
+    fn from_config() -> Option<Self> {
+        let config = fs::read_to_string(CONFIG_PATH).ok()?;
+
+        match ron::from_str::<Config>(&config) {
+            Ok(config) => config.seed.map(Self),
+            Err(err) => {
+                warn!("Couldn't read `{CONFIG_PATH}`: {err}");
+                None
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct SeedText;
+
+fn init_seed_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("font/FiraSans-Bold.ttf"),
+                font_size: 24.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(0.),
+                bottom: Val::Px(0.),
+                ..default()
+            },
+            ..default()
+        })
+        .with_background_color(Color::rgba(0., 0., 0., 0.5)),
+        SeedText,
+    ));
+}
+
+fn update_seed_text(mut texts: Query<&mut Text, With<SeedText>>, seed: Res<WorldSeed>) {
+    if !seed.is_changed() {
+        return;
+    }
+
+    for mut text in &mut texts {
+        text.sections[0].value = format!("Seed: {}", **seed);
+    }
+}
@@ -0,0 +1,107 @@
+use std::marker::PhantomData;
+
+use bevy::utils::HashMap;
+
+use crate::{prelude::*, timestep::TimestepSet};
+
+/// Side length of a cell in the grid
+const CELL_SIZE: f32 = 64.;
+
+pub fn spatial_plugin<T: Component>(app: &mut App) {
+    app.init_resource::<SpatialIndex<T>>().add_system(
+        update_spatial_index::<T>
+            .in_base_set(CoreSet::PostUpdate)
+            .before(TimestepSet::Interpolate),
+    );
+}
+
+/// Grid of the entities with `T`, for finding the ones near a point. Can be a frame behind.
+#[derive(Resource)]
+pub struct SpatialIndex<T: Component> {
+    cells: HashMap<IVec2, Vec<Entity>>,
+    positions: HashMap<Entity, Vec2>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Component> Default for SpatialIndex<T> {
+    fn default() -> Self {
+        Self {
+            cells: default(),
+            positions: default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+fn cell(pos: Vec2) -> IVec2 {
+    (pos / CELL_SIZE).floor().as_ivec2()
+}
+
+impl<T: Component> SpatialIndex<T> {
+    pub fn insert(&mut self, entity: Entity, pos: Vec2) {
+        if let Some(old_pos) = self.positions.insert(entity, pos) {
+            if cell(old_pos) == cell(pos) {
+                return;
+            }
+            self.remove_from_cell(entity, old_pos);
+        }
+
+        self.cells.entry(cell(pos)).or_default().push(entity);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(pos) = self.positions.remove(&entity) {
+            self.remove_from_cell(entity, pos);
+        }
+    }
+
+    fn remove_from_cell(&mut self, entity: Entity, pos: Vec2) {
+        let cell = cell(pos);
+        let Some(entities) = self.cells.get_mut(&cell) else { return };
+        entities.retain(|&other| other != entity);
+        if entities.is_empty() {
+            self.cells.remove(&cell);
+        }
+    }
+
+    pub fn within(&self, pos: Vec2, radius: f32) -> impl Iterator<Item = (Entity, Vec2)> + '_ {
+        let min = cell(pos - radius);
+        let max = cell(pos + radius);
+
+        (min.y..=max.y)
+            .flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .map(|&entity| (entity, self.positions[&entity]))
+            .filter(move |&(_, other_pos)| pos.distance_squared(other_pos) <= radius * radius)
+    }
+
+    /// The closest entity within `radius` that `filter` accepts
+    pub fn nearest(
+        &self,
+        pos: Vec2,
+        radius: f32,
+        filter: impl Fn(Entity, Vec2) -> bool,
+    ) -> Option<(Entity, Vec2)> {
+        self.within(pos, radius)
+            .filter(|&(entity, other_pos)| filter(entity, other_pos))
+            .min_by(|(_, a), (_, b)| {
+                pos.distance_squared(*a)
+                    .total_cmp(&pos.distance_squared(*b))
+            })
+    }
+}
+
+fn update_spatial_index<T: Component>(
+    mut index: ResMut<SpatialIndex<T>>,
+    moved: Query<(Entity, &Transform), (With<T>, Or<(Changed<Transform>, Added<T>)>)>,
+    mut removed: RemovedComponents<T>,
+) {
+    for entity in removed.iter() {
+        index.remove(entity);
+    }
+
+    for (entity, transform) in &moved {
+        index.insert(entity, transform.translation.truncate());
+    }
+}
@@ -1,9 +1,13 @@
-use std::f32::EPSILON;
-
 use bevy::prelude::*;
 use enum_map::{Enum, EnumMap};
 
-use crate::player::Player;
+use crate::{
+    map::{as_object_vec3, Biome},
+    player::Player,
+    prelude::FnPluginExt,
+    seed::WorldSeed,
+    spatial::{spatial_plugin, SpatialIndex},
+};
 
 #[derive(Enum, Clone, Copy, PartialEq, Eq)]
 pub enum Stat {
@@ -39,6 +43,35 @@ impl Stats {
     pub fn calc_damage(&self, f: f32) -> f32 {
         f / self.get(Stat::Health)
     }
+
+    /// Hits aren't resisted
+    pub fn calc_resisted(&self, amount: f32, kind: DamageKind) -> f32 {
+        match kind {
+            DamageKind::Radiation => self.calc_damage(self.calc_radiation_damage(amount)).max(0.),
+            DamageKind::Starvation => self.calc_damage(amount),
+            DamageKind::Kinetic | DamageKind::Energy => amount,
+        }
+    }
+
+    pub fn add_buff(&mut self, buff: Buff) {
+        if let Stacking::Refresh = buff.stacking {
+            if let Some(existing) = self.buffs.iter_mut().find(|other| other.kind == buff.kind) {
+                *existing = buff;
+                return;
+            }
+        }
+
+        self.buffs.push(buff);
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_buffs(&mut self, kind: BuffKind) {
+        self.buffs.retain(|buff| buff.kind != kind);
+    }
+
+    pub fn buffs(&self) -> impl Iterator<Item = &Buff> {
+        self.buffs.iter()
+    }
 }
 
 #[derive(Component, Deref, DerefMut, Reflect, FromReflect)]
@@ -78,27 +111,73 @@ pub struct RadiationSource {
     pub active: bool,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DamageKind {
+    Kinetic,
+    Radiation,
+    Starvation,
+    #[allow(dead_code)]
+    Energy,
+}
+
+impl DamageKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            DamageKind::Kinetic => "kinetic",
+            DamageKind::Radiation => "radiation",
+            DamageKind::Starvation => "starvation",
+            DamageKind::Energy => "energy",
+        }
+    }
+}
+
+/// All health loss goes through these, so resistances are applied in one place
+pub struct Damage {
+    pub target: Entity,
+    /// Before resistances. Negative heals.
+    pub amount: f32,
+    pub kind: DamageKind,
+    pub source: Option<Entity>,
+    /// Marked with a `HitMarker`
+    pub pos: Option<Vec2>,
+}
+
+impl Damage {
+    pub fn new(target: Entity, amount: f32, kind: DamageKind) -> Self {
+        Self {
+            target,
+            amount,
+            kind,
+            source: None,
+            pos: None,
+        }
+    }
+}
+
 pub fn stat_propegation(
-    mut commands: Commands,
-    mut query: Query<(Entity, &mut Stats, &mut Health, &Hunger, &mut Radiation)>,
-    time: Res<Time>,
+    mut query: Query<(Entity, &mut Stats, &Hunger, &mut Radiation)>,
+    mut damages: EventWriter<Damage>,
+    time: Res<FixedTime>,
 ) {
-    // Calculate stats for the frame
-    for (_, mut stats, mut health, _, mut radiation) in query.iter_mut() {
+    let dt = time.period.as_secs_f32();
+
+    // Calculate stats for the tick
+    for (entity, mut stats, _, mut radiation) in query.iter_mut() {
         let mut new_stats = stats.base_stats;
 
         let buffs = stats.buffs.clone();
         stats.buffs.clear();
         for mut buff in buffs.into_iter() {
-            let t = time.delta_seconds().min(buff.total_time - buff.time);
-            buff.time += time.delta_seconds();
+            let t = dt.min(buff.total_time - buff.time);
+            buff.time += dt;
 
             let tick_part = t / buff.total_time;
             buff.effect.apply(
+                entity,
                 &mut new_stats,
                 &stats,
-                &mut health,
                 &mut radiation,
+                &mut damages,
                 tick_part,
             );
 
@@ -109,28 +188,47 @@ pub fn stat_propegation(
         stats.current_stats = new_stats;
     }
     // Do radiation damage
-    for (_, stats, mut health, hunger, mut radiation) in query.iter_mut() {
+    for (entity, _, hunger, mut radiation) in query.iter_mut() {
         if radiation.0 > 0.8 {
-            health.0 -= stats
-                .calc_damage(
-                    stats.calc_radiation_damage((radiation.0 - 0.8) * time.delta_seconds()),
-                )
-                .max(0.);
+            damages.send(Damage::new(
+                entity,
+                (radiation.0 - 0.8) * dt,
+                DamageKind::Radiation,
+            ));
         }
 
-        if hunger.0 <= EPSILON {
-            health.0 -= stats.calc_damage(time.delta_seconds());
+        if hunger.0 <= f32::EPSILON {
+            damages.send(Damage::new(entity, dt, DamageKind::Starvation));
         }
 
         if radiation.0 > 0.0 {
-            radiation.0 -= time.delta_seconds() * 0.003;
+            radiation.0 -= dt * 0.003;
         }
         radiation.0 = radiation.0.clamp(0.0, 1.0);
     }
+}
+
+pub fn take_damage(
+    mut commands: Commands,
+    mut healths: Query<(Entity, &mut Health, Option<&Stats>, Option<&Player>)>,
+    mut damages: EventReader<Damage>,
+) {
+    for damage in damages.iter() {
+        let Ok((_, mut health, stats, _)) = healths.get_mut(damage.target) else { continue };
+        **health -= match stats {
+            Some(stats) => stats.calc_resisted(damage.amount, damage.kind),
+            None => damage.amount,
+        };
+    }
 
     // Kill and adjust health.
-    for (entity, _, mut health, _, _) in query.iter_mut() {
-        if health.0 <= 0.0 {
+    for (entity, mut health, stats, player) in healths.iter_mut() {
+        if stats.is_none() {
+            continue;
+        }
+
+        // The player's body is left for `restart`
+        if health.0 <= 0.0 && player.is_none() {
             commands.entity(entity).despawn();
         }
         health.0 = health.0.clamp(0.0, 1.0);
@@ -140,43 +238,109 @@ pub fn stat_propegation(
 #[derive(Clone, Copy)]
 pub enum EffectKind {
     Mul(Stat),
+    #[allow(dead_code)]
     Add(Stat),
-    Health,
+    /// Spread over the buff's duration. Negative strength heals.
+    #[allow(dead_code)]
+    Damage(DamageKind),
+    #[allow(dead_code)]
     Radiation,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy)]
 pub struct Effect {
-    kind: EffectKind,
-    strength: f32,
+    pub kind: EffectKind,
+    pub strength: f32,
 }
 
 impl Effect {
+    pub fn new(kind: EffectKind, strength: f32) -> Self {
+        Self { kind, strength }
+    }
+
     fn apply(
         &self,
+        entity: Entity,
         new_stats: &mut EnumMap<Stat, f32>,
         stats: &Stats,
-        health: &mut Health,
         radiation: &mut Radiation,
+        damages: &mut EventWriter<Damage>,
         tick_part: f32,
     ) {
         match self.kind {
             EffectKind::Mul(stat) => new_stats[stat] *= self.strength,
             EffectKind::Add(stat) => new_stats[stat] += self.strength,
-            EffectKind::Health => health.0 += stats.calc_damage(self.strength * tick_part),
+            EffectKind::Damage(kind) => {
+                damages.send(Damage::new(entity, self.strength * tick_part, kind))
+            }
             EffectKind::Radiation => {
                 radiation.0 += stats.calc_radiation_damage(self.strength * tick_part)
             }
         }
     }
 }
-#[derive(Clone)]
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum BuffKind {
+    Slowed,
+}
+
+impl BuffKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            BuffKind::Slowed => "Slowed",
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            BuffKind::Slowed => Color::rgb(0.3, 0.4, 0.8),
+        }
+    }
+}
+
+/// When an entity already has a buff of the kind
+#[derive(Clone, Copy)]
+pub enum Stacking {
+    /// Both apply
+    #[allow(dead_code)]
+    Stack,
+    /// The old one starts over
+    Refresh,
+}
+
+#[derive(Clone, Copy)]
 pub struct Buff {
-    effect: Effect,
+    pub kind: BuffKind,
+    pub effect: Effect,
+    pub stacking: Stacking,
     total_time: f32,
     time: f32,
 }
 
+impl Buff {
+    pub fn new(kind: BuffKind, effect: Effect, duration: f32, stacking: Stacking) -> Self {
+        Self {
+            kind,
+            effect,
+            stacking,
+            total_time: duration,
+            time: 0.,
+        }
+    }
+
+    pub fn remaining(&self) -> f32 {
+        (self.total_time - self.time).max(0.)
+    }
+}
+
+pub fn add_buff(entity: Entity, buff: Buff) -> impl Fn(&mut World) {
+    move |world| {
+        let Some(mut stats) = world.get_mut::<Stats>(entity) else { return };
+        stats.add_buff(buff);
+    }
+}
+
 #[derive(Bundle, Default)]
 pub struct StatBundle {
     pub stats: Stats,
@@ -194,12 +358,38 @@ struct HungerBar;
 #[derive(Component)]
 struct RadiationBar;
 
+#[derive(Component)]
+struct BiomeText;
+
+#[derive(Component)]
+struct BuffIcons;
+
+#[derive(Component)]
+struct BuffIconText(usize);
+
+const HIT_MARKER_TIME: f32 = 0.25;
+
+/// Holds the seconds it has left
+#[derive(Component)]
+struct HitMarker(f32);
+
 pub fn stat_plugin(app: &mut App) {
-    app.add_startup_system(init_ui)
-        .add_system(stat_propegation)
+    app.fn_plugin(spatial_plugin::<Radiation>)
+        .add_startup_system(init_ui)
         .add_system(update_ui)
-        .add_system(absorb_radiation)
-        .add_system(get_hungry);
+        .add_system(update_buff_icons)
+        .add_system(fade_hit_markers)
+        .add_event::<Damage>()
+        .add_systems(
+            (
+                stat_propegation,
+                take_damage.after(stat_propegation),
+                spawn_hit_markers.after(take_damage),
+                absorb_radiation,
+                get_hungry,
+            )
+                .in_schedule(CoreSchedule::FixedUpdate),
+        );
 
     app.register_type::<Health>().register_type::<Radiation>();
 }
@@ -257,13 +447,36 @@ fn init_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
                         TextBundle::from_section(
                             "Radiation: 0",
                             TextStyle {
-                                font,
+                                font: font.clone(),
                                 font_size: 40.0,
                                 color: Color::GREEN,
                             },
                         ),
                         RadiationBar,
                     ));
+
+                    parent.spawn((
+                        TextBundle::from_section(
+                            "",
+                            TextStyle {
+                                font,
+                                font_size: 24.0,
+                                color: Color::WHITE,
+                            },
+                        ),
+                        BiomeText,
+                    ));
+
+                    parent.spawn((
+                        NodeBundle {
+                            style: Style {
+                                flex_wrap: FlexWrap::Wrap,
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        BuffIcons,
+                    ));
                 });
         });
 }
@@ -272,9 +485,19 @@ fn update_ui(
     mut healths: Query<&mut Text, (With<HealthBar>, Without<HungerBar>, Without<RadiationBar>)>,
     mut hungers: Query<&mut Text, (With<HungerBar>, Without<HealthBar>, Without<RadiationBar>)>,
     mut radiations: Query<&mut Text, (With<RadiationBar>, Without<HealthBar>, Without<HungerBar>)>,
-    players: Query<(&Health, &Hunger, &Radiation), With<Player>>,
+    mut biomes: Query<
+        &mut Text,
+        (
+            With<BiomeText>,
+            Without<HealthBar>,
+            Without<HungerBar>,
+            Without<RadiationBar>,
+        ),
+    >,
+    players: Query<(&Health, &Hunger, &Radiation, &Transform), With<Player>>,
+    seed: Res<WorldSeed>,
 ) {
-    let Ok((health, hunger, radiation)) = players.get_single() else { return };
+    let Ok((health, hunger, radiation, transform)) = players.get_single() else { return };
 
     let mut health_text = healths.single_mut();
     health_text.sections[0].value = format!("Health: {}", (health.0 * 100.).ceil());
@@ -284,34 +507,178 @@ fn update_ui(
 
     let mut radiation_text = radiations.single_mut();
     radiation_text.sections[0].value = format!("Radiation: {}", (radiation.0 * 100.).ceil());
+
+    let mut biome_text = biomes.single_mut();
+    let biome = Biome::at(transform.translation.truncate(), **seed);
+    biome_text.sections[0].value = biome.name().to_owned();
+}
+
+fn spawn_hit_markers(mut commands: Commands, mut damages: EventReader<Damage>) {
+    for damage in damages.iter() {
+        let Some(pos) = damage.pos else { continue };
+        if damage.amount <= 0. {
+            continue;
+        }
+
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb(1., 0.3, 0.2),
+                    custom_size: Some(Vec2::splat(6.)),
+                    ..default()
+                },
+                transform: Transform::from_translation(as_object_vec3(pos)),
+                ..default()
+            },
+            HitMarker(HIT_MARKER_TIME),
+        ));
+    }
+}
+
+fn fade_hit_markers(
+    mut commands: Commands,
+    mut markers: Query<(Entity, &mut HitMarker, &mut Sprite)>,
+    time: Res<Time>,
+) {
+    for (entity, mut marker, mut sprite) in &mut markers {
+        marker.0 -= time.delta_seconds();
+        if marker.0 <= 0. {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        sprite.color.set_a(marker.0 / HIT_MARKER_TIME);
+    }
+}
+
+fn update_buff_icons(
+    mut commands: Commands,
+    buff_icons: Query<Entity, With<BuffIcons>>,
+    mut texts: Query<(&BuffIconText, &mut Text)>,
+    players: Query<&Stats, With<Player>>,
+    mut shown: Local<Vec<BuffKind>>,
+    asset_server: Res<AssetServer>,
+) {
+    let buffs = match players.get_single() {
+        Ok(stats) => stats.buffs().collect(),
+        Err(_) => Vec::new(),
+    };
+    let label = |buff: &Buff| format!("{} {:.1}", buff.kind.name(), buff.remaining());
+
+    // Only rebuild the icons when the buffs change. Otherwise, just count down.
+    if buffs.iter().map(|buff| buff.kind).eq(shown.iter().copied()) {
+        for (&BuffIconText(index), mut text) in &mut texts {
+            if let Some(buff) = buffs.get(index) {
+                text.sections[0].value = label(buff);
+            }
+        }
+        return;
+    }
+
+    *shown = buffs.iter().map(|buff| buff.kind).collect();
+    let buff_icons = buff_icons.single();
+    commands.entity(buff_icons).despawn_descendants();
+    let font = asset_server.load("font/FiraSans-Bold.ttf");
+
+    for (index, buff) in buffs.into_iter().enumerate() {
+        let icon = commands
+            .spawn(NodeBundle {
+                style: Style {
+                    padding: UiRect::all(Val::Px(4.)),
+                    margin: UiRect::all(Val::Px(2.)),
+                    ..default()
+                },
+                background_color: buff.kind.color().into(),
+                ..default()
+            })
+            .with_children(|icon| {
+                icon.spawn((
+                    TextBundle::from_section(
+                        label(buff),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 20.0,
+                            color: Color::WHITE,
+                        },
+                    ),
+                    BuffIconText(index),
+                ));
+            })
+            .id();
+
+        commands.entity(buff_icons).add_child(icon);
+    }
 }
 
 fn absorb_radiation(
     mut consumers: Query<(&mut Radiation, &Stats, &Transform)>,
     sources: Query<(&RadiationSource, &Transform)>,
-    time: Res<Time>,
+    consumer_index: Res<SpatialIndex<Radiation>>,
+    seed: Res<WorldSeed>,
+    time: Res<FixedTime>,
 ) {
+    let dt = time.period.as_secs_f32();
+
     for (mut radiation, stats, consumer_transform) in consumers.iter_mut() {
-        for (source, source_transform) in sources.iter() {
-            if source.active
-                && source_transform
-                    .translation
-                    .distance_squared(consumer_transform.translation)
-                    < source.radius.powi(2)
-            {
-                **radiation +=
-                    source.strength / stats.get(Stat::RadiationResistence) * time.delta_seconds();
-                **radiation = radiation.clamp(0., 1.);
-            }
+        let biome = Biome::at(consumer_transform.translation.truncate(), **seed);
+        **radiation += biome.radiation() / stats.get(Stat::RadiationResistence) * dt;
+        **radiation = radiation.clamp(0., 1.);
+    }
+
+    for (source, source_transform) in sources.iter() {
+        if !source.active {
+            continue;
+        }
+
+        let source_pos = source_transform.translation.truncate();
+        for (consumer, _) in consumer_index.within(source_pos, source.radius) {
+            let Ok((mut radiation, stats, _)) = consumers.get_mut(consumer) else { continue };
+            **radiation += source.strength / stats.get(Stat::RadiationResistence) * dt;
+            **radiation = radiation.clamp(0., 1.);
         }
     }
 }
 
 const HUNGER_RATE: f32 = 0.005;
 
-fn get_hungry(mut hungers: Query<&mut Hunger>, time: Res<Time>) {
+fn get_hungry(mut hungers: Query<&mut Hunger>, time: Res<FixedTime>) {
     for mut hunger in hungers.iter_mut() {
-        **hunger -= HUNGER_RATE * time.delta_seconds();
+        **hunger -= HUNGER_RATE * time.period.as_secs_f32();
         **hunger = hunger.clamp(0., 1.);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slowed(duration: f32, stacking: Stacking) -> Buff {
+        Buff::new(
+            BuffKind::Slowed,
+            Effect::new(EffectKind::Mul(Stat::Speed), 0.5),
+            duration,
+            stacking,
+        )
+    }
+
+    #[test]
+    fn stacks_refreshes_and_removes_buffs() {
+        let mut world = World::new();
+        let entity = world.spawn(Stats::default()).id();
+
+        add_buff(entity, slowed(1., Stacking::Stack))(&mut world);
+        add_buff(entity, slowed(2., Stacking::Stack))(&mut world);
+        let stats = world.get::<Stats>(entity).unwrap();
+        assert_eq!(stats.buffs().count(), 2);
+
+        // Refreshes the first one instead of adding a third
+        add_buff(entity, slowed(3., Stacking::Refresh))(&mut world);
+        let stats = world.get::<Stats>(entity).unwrap();
+        let remaining = stats.buffs().map(Buff::remaining).collect::<Vec<_>>();
+        assert_eq!(remaining, [3., 2.]);
+
+        let mut stats = world.get_mut::<Stats>(entity).unwrap();
+        stats.remove_buffs(BuffKind::Slowed);
+        assert_eq!(stats.buffs().count(), 0);
+    }
+}
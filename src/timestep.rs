@@ -0,0 +1,82 @@
+use bevy::transform::TransformSystem;
+
+use crate::{
+    physics::{entity_terrain, Vel},
+    prelude::*,
+};
+
+/// Gameplay runs in `CoreSchedule::FixedUpdate`, this many times per second
+const TICK_RATE: f32 = 60.;
+
+pub fn timestep_plugin(app: &mut App) {
+    app.insert_resource(FixedTime::new_from_secs(1. / TICK_RATE))
+        .configure_set(
+            TimestepSet::Interpolate
+                .in_base_set(CoreSet::PostUpdate)
+                .before(TransformSystem::TransformPropagate),
+        )
+        .add_system(restore_translations.in_base_set(CoreSet::First))
+        .add_system(
+            store_translations
+                .before(entity_terrain)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        )
+        .add_system(interpolate_translations.in_set(TimestepSet::Interpolate));
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, SystemSet)]
+pub enum TimestepSet {
+    /// Before this, transforms are where gameplay left them
+    Interpolate,
+}
+
+/// Smooths movement between ticks. Added to everything with `Vel`.
+#[derive(Component)]
+pub struct Interpolated {
+    previous: Vec3,
+    current: Vec3,
+}
+
+fn store_translations(
+    mut commands: Commands,
+    mut movers: Query<(Entity, &Transform, Option<&mut Interpolated>), With<Vel>>,
+) {
+    for (entity, transform, interpolated) in &mut movers {
+        let translation = transform.translation;
+        match interpolated {
+            Some(mut interpolated) => interpolated.previous = translation,
+            None => {
+                commands.entity(entity).insert(Interpolated {
+                    previous: translation,
+                    current: translation,
+                });
+            }
+        }
+    }
+}
+
+/// Doesn't mark transforms as changed, so `Changed<Transform>` only sees gameplay moving things
+fn interpolate_translations(
+    mut interpolateds: Query<
+        (&mut Transform, &mut GlobalTransform, &mut Interpolated),
+        Without<Parent>,
+    >,
+    time: Res<FixedTime>,
+) {
+    let alpha = time.accumulated().as_secs_f32() / time.period.as_secs_f32();
+
+    for (mut transform, mut global_transform, mut interpolated) in &mut interpolateds {
+        let transform = transform.bypass_change_detection();
+        interpolated.current = transform.translation;
+        transform.translation = interpolated.previous.lerp(interpolated.current, alpha);
+        // Propagation skips unchanged transforms
+        *global_transform = (*transform).into();
+    }
+}
+
+/// Puts transforms back where gameplay left them, before anything else sees them
+fn restore_translations(mut interpolateds: Query<(&mut Transform, &Interpolated)>) {
+    for (mut transform, interpolated) in &mut interpolateds {
+        transform.bypass_change_detection().translation = interpolated.current;
+    }
+}